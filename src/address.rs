@@ -0,0 +1,248 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use tiny_keccak::{Hasher, Keccak};
+
+/// Represents a 20 byte account address. Parsing accepts all lowercase and all uppercase hex,
+/// mixed case has to be a valid EIP-55 checksum. Addresses compare, display and serialize
+/// lowercase no matter how they were written, checksum() gives the EIP-55 form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address([u8; 20]);
+
+/// Represents why a string isn't an address
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseAddressError {
+    MissingPrefix,
+    Length(usize),
+    InvalidHex,
+    Checksum,
+}
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseAddressError::MissingPrefix => write!(f, "address must start with 0x"),
+            ParseAddressError::Length(n) => write!(f, "address has {} hex digits, not 40", n),
+            ParseAddressError::InvalidHex => write!(f, "address isn't hex"),
+            ParseAddressError::Checksum => write!(f, "address has an invalid EIP-55 checksum"),
+        }
+    }
+}
+
+impl std::error::Error for ParseAddressError {}
+
+impl Address {
+    pub fn zero() -> Self {
+        Address([0; 20])
+    }
+
+    pub fn from_bytes(bytes: [u8; 20]) -> Self {
+        Address(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Returns the address in the last 20 bytes of a 32 byte hex word, as found in log topics
+    /// and abi encoded data. The first 12 bytes have to be zero.
+    pub fn from_word(word: &str) -> Option<Self> {
+        let digits = word.strip_prefix("0x").unwrap_or(word);
+        if digits.len() != 64
+            || !digits.bytes().all(|b| b.is_ascii_hexdigit())
+            || !digits[..24].chars().all(|c| c == '0')
+        {
+            return None;
+        }
+        format!("0x{}", &digits[24..]).to_lowercase().parse().ok()
+    }
+
+    /// Returns the address abi encoded as a 32 byte word, without 0x prefix
+    pub fn to_word(self) -> String {
+        format!("{:0>64}", hex(&self.0))
+    }
+
+    /// Returns the EIP-55 mixed case checksum form. A hex letter is uppercase when the matching
+    /// nibble of the keccak hash of the lowercase address is 8 or higher.
+    pub fn checksum(&self) -> String {
+        let lower = hex(&self.0);
+        let mut keccak = Keccak::v256();
+        keccak.update(lower.as_bytes());
+        let mut hash = [0u8; 32];
+        keccak.finalize(&mut hash);
+
+        let digits: String = lower
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0xf;
+                if nibble >= 8 {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                }
+            })
+            .collect();
+        format!("0x{}", digits)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl FromStr for Address {
+    type Err = ParseAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .ok_or(ParseAddressError::MissingPrefix)?;
+        if digits.len() != 40 {
+            return Err(ParseAddressError::Length(digits.len()));
+        }
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseAddressError::InvalidHex);
+        }
+
+        let mut bytes = [0u8; 20];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| ParseAddressError::InvalidHex)?;
+        }
+        let address = Address(bytes);
+
+        let has_lower = digits.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = digits.chars().any(|c| c.is_ascii_uppercase());
+        if has_lower && has_upper && address.checksum()[2..] != *digits {
+            return Err(ParseAddressError::Checksum);
+        }
+        Ok(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", hex(&self.0))
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_eip55_vectors() {
+        for checksummed in &[
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let address: Address = checksummed.parse().unwrap();
+            assert_eq!(address.checksum(), *checksummed);
+            assert_eq!(address.to_string(), checksummed.to_lowercase());
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |s: &str| s.parse::<Address>().unwrap_err();
+        assert_eq!(
+            parse("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            ParseAddressError::MissingPrefix
+        );
+        assert_eq!(parse("0x5aaeb6"), ParseAddressError::Length(6));
+        assert_eq!(
+            parse("0xzaaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            ParseAddressError::InvalidHex
+        );
+        assert_eq!(
+            parse("0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"),
+            ParseAddressError::Checksum
+        );
+    }
+
+    #[test]
+    fn test_words() {
+        let address: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"
+            .parse()
+            .unwrap();
+        let word = address.to_word();
+        assert_eq!(word.len(), 64);
+        assert_eq!(Address::from_word(&format!("0x{}", word)), Some(address));
+        assert_eq!(Address::from_word(&format!("0x1{}", &word[1..])), None);
+        assert_eq!(Address::from_word("0x01"), None);
+        // 64 bytes with a two byte character across byte 24
+        let word = format!("{}é{}", "0".repeat(23), "a".repeat(39));
+        assert_eq!(word.len(), 64);
+        assert_eq!(Address::from_word(&word), None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roundtrips(bytes: [u8; 20]) {
+            let address = Address::from_bytes(bytes);
+            let lower = address.to_string();
+            prop_assert_eq!(lower.parse::<Address>(), Ok(address));
+            prop_assert_eq!(address.checksum().parse::<Address>(), Ok(address));
+            prop_assert_eq!(lower.to_uppercase().replacen("0X", "0x", 1).parse::<Address>(), Ok(address));
+            prop_assert_eq!(address.checksum().to_lowercase(), lower);
+
+            let json = serde_json::to_string(&address).unwrap();
+            prop_assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+        }
+
+        #[test]
+        fn prop_from_word_rejects_non_hex(prefix in "[0-9a-f]{0,62}", c in "[^0-9a-fA-F]") {
+            // Multi-byte characters can straddle the byte offsets the word is split at
+            let mut word = format!("{}{}", prefix, c);
+            while word.len() < 64 {
+                word.push('0');
+            }
+            prop_assume!(word.len() == 64);
+            prop_assert_eq!(Address::from_word(&word), None);
+            prop_assert_eq!(Address::from_word(&format!("0x{}", word)), None);
+        }
+
+        #[test]
+        fn prop_detects_changed_case(bytes: [u8; 20], ix in 0usize..40) {
+            let checksummed = Address::from_bytes(bytes).checksum();
+            let digits: Vec<char> = checksummed[2..].chars().collect();
+            let letters = digits.iter().filter(|c| c.is_ascii_alphabetic()).count();
+            let lower = digits.iter().filter(|c| c.is_ascii_lowercase()).count();
+            // Flipping one letter only breaks the checksum while the result is still mixed case
+            prop_assume!(digits[ix].is_ascii_alphabetic() && letters > 1);
+            prop_assume!(if digits[ix].is_ascii_lowercase() { lower > 1 } else { letters - lower > 1 });
+
+            let mut flipped = digits.clone();
+            flipped[ix] = if flipped[ix].is_ascii_lowercase() {
+                flipped[ix].to_ascii_uppercase()
+            } else {
+                flipped[ix].to_ascii_lowercase()
+            };
+            let flipped: String = flipped.into_iter().collect();
+            prop_assert_eq!(
+                format!("0x{}", flipped).parse::<Address>(),
+                Err(ParseAddressError::Checksum)
+            );
+        }
+    }
+}
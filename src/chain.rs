@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Represents a chain merter can scan. The name is used on the command line, in the database
+/// and as folder in the storage path.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Chain {
+    pub name: String,
+    pub chain_id: u64,
+    pub native_symbol: String,
+    #[serde(default = "default_decimals")]
+    pub decimals: usize,
+    /// Base url of the etherscan compatible api of the block explorer
+    pub explorer_api: String,
+    /// Name of the config file, defaults to .<name>conf.toml
+    #[serde(default)]
+    pub config_file: String,
+}
+
+/// Represents chains.toml, where chains that aren't built in can be added or built-in ones
+/// overridden:
+///
+/// ```toml
+/// [[chain]]
+/// name = "gnosis"
+/// chain_id = 100
+/// native_symbol = "XDAI"
+/// explorer_api = "https://api.gnosisscan.io/api"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct ChainsFile {
+    #[serde(default)]
+    chain: Vec<Chain>,
+}
+
+/// Decimals of the native coin of chains that don't set them
+pub const DEFAULT_DECIMALS: usize = 18;
+
+fn default_decimals() -> usize {
+    DEFAULT_DECIMALS
+}
+
+impl Chain {
+    fn new(name: &str, chain_id: u64, native_symbol: &str, explorer_api: &str) -> Self {
+        Chain {
+            name: name.to_string(),
+            chain_id,
+            native_symbol: native_symbol.to_string(),
+            decimals: default_decimals(),
+            explorer_api: explorer_api.to_string(),
+            config_file: format!(".{}conf.toml", name),
+        }
+    }
+
+    /// Returns the chain called `name`, looking at chains.toml first and the built-in chains
+    /// second
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        find(&all(), name)
+    }
+}
+
+/// Returns the chain called `name` from a list of chains, ignoring case
+fn find(chains: &[Chain], name: &str) -> Result<Chain, String> {
+    chains
+        .iter()
+        .find(|chain| chain.name.eq_ignore_ascii_case(name))
+        .cloned()
+        .ok_or_else(|| {
+            let names: Vec<&str> = chains.iter().map(|chain| chain.name.as_str()).collect();
+            format!("unknown chain {}, use one of {}", name, names.join(", "))
+        })
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Returns the chains that are built into merter
+pub fn builtin() -> Vec<Chain> {
+    vec![
+        Chain::new("eth", 1, "ETH", "https://api.etherscan.io/api"),
+        Chain::new("bsc", 56, "BNB", "https://api.bscscan.com/api"),
+        Chain::new("polygon", 137, "POL", "https://api.polygonscan.com/api"),
+        Chain::new("arbitrum", 42161, "ETH", "https://api.arbiscan.io/api"),
+        Chain::new(
+            "optimism",
+            10,
+            "ETH",
+            "https://api-optimistic.etherscan.io/api",
+        ),
+        Chain::new("avalanche", 43114, "AVAX", "https://api.snowtrace.io/api"),
+        Chain::new("fantom", 250, "FTM", "https://api.ftmscan.com/api"),
+    ]
+}
+
+/// Returns the built-in chains merged with the ones in chains.toml. The chains.toml in the
+/// executable's working directory takes precedence over the one in the config dir.
+pub fn all() -> Vec<Chain> {
+    let mut chains = builtin();
+    for path in chains_paths() {
+        match load(&path) {
+            Ok(user) => merge(&mut chains, user),
+            Err(e) => println!("Error: Couldn't read {} \n{}", path.display(), e),
+        }
+    }
+    chains
+}
+
+/// Reads the chains in a chains.toml, a missing file has no chains
+fn load(path: &Path) -> Result<Vec<Chain>, Box<dyn Error + Send + Sync>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    parse(&std::fs::read_to_string(path)?)
+}
+
+fn parse(toml: &str) -> Result<Vec<Chain>, Box<dyn Error + Send + Sync>> {
+    let file: ChainsFile = toml::from_str(toml)?;
+    let mut chains = file.chain;
+    for chain in &mut chains {
+        chain.name = chain.name.to_lowercase();
+        if chain.name.is_empty() || !chain.name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("invalid chain name {:?}", chain.name).into());
+        }
+        if chain.config_file.is_empty() {
+            chain.config_file = format!(".{}conf.toml", chain.name);
+        }
+    }
+    Ok(chains)
+}
+
+/// Adds the user chains, a chain with the same name as an existing one replaces it
+fn merge(chains: &mut Vec<Chain>, user: Vec<Chain>) {
+    for chain in user {
+        match chains.iter_mut().find(|c| c.name == chain.name) {
+            Some(existing) => *existing = chain,
+            None => chains.push(chain),
+        }
+    }
+}
+
+fn chains_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(mut config_dir) = dirs::config_dir() {
+        config_dir.push("merter");
+        config_dir.push("chains.toml");
+        paths.push(config_dir);
+    }
+    if let Ok(mut exe_path) = std::env::current_exe() {
+        exe_path.pop();
+        exe_path.push("chains.toml");
+        paths.push(exe_path);
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let chains = builtin();
+        let mut ids: Vec<u64> = chains.iter().map(|chain| chain.chain_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), chains.len());
+
+        let bsc = find(&chains, "BSC").unwrap();
+        assert_eq!(bsc.chain_id, 56);
+        assert_eq!(bsc.native_symbol, "BNB");
+        assert_eq!(bsc.config_file, ".bscconf.toml");
+        assert!(find(&chains, "dogechain")
+            .unwrap_err()
+            .contains("use one of eth, bsc"));
+    }
+
+    #[test]
+    fn test_user_chains() {
+        let user = parse(
+            r#"
+            [[chain]]
+            name = "Gnosis"
+            chain_id = 100
+            native_symbol = "XDAI"
+            explorer_api = "https://api.gnosisscan.io/api"
+
+            [[chain]]
+            name = "eth"
+            chain_id = 1
+            native_symbol = "ETH"
+            explorer_api = "http://localhost:8080/api"
+            config_file = "mainnet.toml"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(user[0].name, "gnosis");
+        assert_eq!(user[0].decimals, 18);
+        assert_eq!(user[0].config_file, ".gnosisconf.toml");
+
+        let mut chains = builtin();
+        merge(&mut chains, user);
+        assert_eq!(chains.len(), builtin().len() + 1);
+        assert_eq!(chains[0].explorer_api, "http://localhost:8080/api");
+        assert_eq!(chains[0].config_file, "mainnet.toml");
+        assert_eq!(chains.last().unwrap().name, "gnosis");
+
+        assert!(parse(
+            "[[chain]]\nname = \"../x\"\nchain_id = 1\nnative_symbol = \"X\"\nexplorer_api = \"\""
+        )
+        .is_err());
+        assert!(load(Path::new("/nonexistent/chains.toml"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use super::address::Address;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Runtime code of an EIP-1167 minimal proxy, the implementation address goes in between
+const MINIMAL_PROXY_PREFIX: &str = "363d3d373d3d3d363d73";
+const MINIMAL_PROXY_SUFFIX: &str = "5af43d82803e903d91602b57fd5bf3";
+
+/// Code an EIP-7702 delegated account gets, followed by the address it delegates to
+const DELEGATION_PREFIX: &str = "ef0100";
+
+/// Highest precompile address, 0x01 up to 0x11 after Prague
+const LAST_PRECOMPILE: u8 = 0x11;
+
+/// Represents what lives at an address, classified from its code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeKind {
+    /// No code, an externally owned account
+    Eoa,
+    Contract,
+    /// EIP-1167 minimal proxy that forwards every call to the implementation
+    MinimalProxy(Address),
+    /// EIP-7702 account that runs the code of the delegate
+    Delegated(Address),
+    /// Precompiled contract, has no code but isn't an account
+    Precompile,
+    /// No code anymore, while the address was a contract before
+    Destroyed,
+    /// The node refused to give the code, so what lives there is unknown
+    Invalid,
+}
+
+impl CodeKind {
+    /// Classifies an address by the code eth_getCode returned for it
+    pub fn classify(address: &Address, code: &str) -> Self {
+        let bytes = address.as_bytes();
+        if bytes[..19].iter().all(|b| *b == 0) && (1..=LAST_PRECOMPILE).contains(&bytes[19]) {
+            return CodeKind::Precompile;
+        }
+
+        let code = code.trim_start_matches("0x").to_lowercase();
+        if code.is_empty() {
+            return CodeKind::Eoa;
+        }
+        if let Some(delegate) = code
+            .strip_prefix(DELEGATION_PREFIX)
+            .and_then(|rest| format!("0x{}", rest).parse().ok())
+        {
+            return CodeKind::Delegated(delegate);
+        }
+        if let Some(implementation) = code
+            .strip_prefix(MINIMAL_PROXY_PREFIX)
+            .and_then(|rest| rest.strip_suffix(MINIMAL_PROXY_SUFFIX))
+            .and_then(|target| format!("0x{}", target).parse().ok())
+        {
+            return CodeKind::MinimalProxy(implementation);
+        }
+        CodeKind::Contract
+    }
+
+    /// Returns the kind of an address that was known as a contract before, having no code
+    /// now means it was destroyed
+    pub fn since(self, was_contract: bool) -> Self {
+        match self {
+            CodeKind::Eoa if was_contract => CodeKind::Destroyed,
+            kind => kind,
+        }
+    }
+
+    /// Returns true for addresses with code of their own worth analyzing
+    pub fn is_contract(self) -> bool {
+        matches!(self, CodeKind::Contract | CodeKind::MinimalProxy(_))
+    }
+}
+
+impl fmt::Display for CodeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodeKind::Eoa => write!(f, "eoa"),
+            CodeKind::Contract => write!(f, "contract"),
+            CodeKind::MinimalProxy(implementation) => write!(f, "minimal-proxy {}", implementation),
+            CodeKind::Delegated(delegate) => write!(f, "delegated {}", delegate),
+            CodeKind::Precompile => write!(f, "precompile"),
+            CodeKind::Destroyed => write!(f, "destroyed"),
+            CodeKind::Invalid => write!(f, "invalid"),
+        }
+    }
+}
+
+/// Parses what Display writes, used to read kinds back from the database
+impl FromStr for CodeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let target = |address: &str| {
+            address
+                .parse()
+                .map_err(|e| format!("invalid code kind {:?}, {}", s, e))
+        };
+        match s.split_once(' ') {
+            None => match s {
+                "eoa" => Ok(CodeKind::Eoa),
+                "contract" => Ok(CodeKind::Contract),
+                "precompile" => Ok(CodeKind::Precompile),
+                "destroyed" => Ok(CodeKind::Destroyed),
+                "invalid" => Ok(CodeKind::Invalid),
+                _ => Err(format!("invalid code kind {:?}", s)),
+            },
+            Some(("minimal-proxy", address)) => Ok(CodeKind::MinimalProxy(target(address)?)),
+            Some(("delegated", address)) => Ok(CodeKind::Delegated(target(address)?)),
+            Some(_) => Err(format!("invalid code kind {:?}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCOUNT: &str = "0x1111111111111111111111111111111111111111";
+    const TARGET: &str = "0xbebebebebebebebebebebebebebebebebebebebe";
+
+    fn classify(address: &str, code: &str) -> CodeKind {
+        CodeKind::classify(&address.parse().unwrap(), code)
+    }
+
+    #[test]
+    fn test_classify() {
+        let target: Address = TARGET.parse().unwrap();
+        assert_eq!(classify(ACCOUNT, "0x"), CodeKind::Eoa);
+        assert_eq!(classify(ACCOUNT, "0x6080604052"), CodeKind::Contract);
+        assert_eq!(
+            classify(
+                ACCOUNT,
+                "0x363d3d373d3d3d363d73bebebebebebebebebebebebebebebebebebebebe5af43d82803e903d91602b57fd5bf3"
+            ),
+            CodeKind::MinimalProxy(target)
+        );
+        assert_eq!(
+            classify(ACCOUNT, &format!("0xEF0100{}", &TARGET[2..].to_uppercase())),
+            CodeKind::Delegated(target)
+        );
+        // Code that only starts like a delegation is an ordinary contract
+        assert_eq!(classify(ACCOUNT, "0xef0100be"), CodeKind::Contract);
+        assert_eq!(
+            classify("0x0000000000000000000000000000000000000001", "0x"),
+            CodeKind::Precompile
+        );
+        assert_eq!(
+            classify("0x0000000000000000000000000000000000000012", "0x"),
+            CodeKind::Eoa
+        );
+
+        assert_eq!(CodeKind::Eoa.since(true), CodeKind::Destroyed);
+        assert_eq!(CodeKind::Eoa.since(false), CodeKind::Eoa);
+        assert_eq!(CodeKind::Contract.since(true), CodeKind::Contract);
+    }
+
+    #[test]
+    fn test_display_roundtrips() {
+        let target: Address = TARGET.parse().unwrap();
+        for kind in &[
+            CodeKind::Eoa,
+            CodeKind::Contract,
+            CodeKind::MinimalProxy(target),
+            CodeKind::Delegated(target),
+            CodeKind::Precompile,
+            CodeKind::Destroyed,
+            CodeKind::Invalid,
+        ] {
+            assert_eq!(kind.to_string().parse::<CodeKind>(), Ok(*kind));
+        }
+        assert!("proxy".parse::<CodeKind>().is_err());
+        assert!("delegated 0x12".parse::<CodeKind>().is_err());
+    }
+}
@@ -1,27 +1,68 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::code::CodeKind;
+use super::db;
 use super::jsonrpc;
+use super::pool::Pool;
 use super::settings;
-use super::timers;
+use super::units;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::ffi::OsStr;
 use std::path::Path;
 
 use futures::stream::{FuturesUnordered, StreamExt};
+use primitive_types::U256;
+use tiny_keccak::{Hasher, Keccak};
+
+/// Decimals balances in a holder list are parsed with, token balances have at most 18 in
+/// practice, the rest leaves room for tokens that use more
+pub const BALANCE_DECIMALS: usize = 36;
+
+/// Header names of the address and balance columns, lowercase and without spaces
+const ADDRESS_HEADERS: [&str; 4] = ["holderaddress", "address", "holder", "account"];
+const BALANCE_HEADERS: [&str; 4] = ["balance", "quantity", "amount", "value"];
+
+/// Represents a holder in a holder list
+#[derive(Default, Debug, PartialEq)]
+pub struct Entry {
+    pub address: Address,
+    /// Balance with BALANCE_DECIMALS decimals
+    pub balance: U256,
+}
+
+/// Represents a row of a holder list that couldn't be read
+#[derive(Debug, PartialEq)]
+struct BadRow {
+    line: u64,
+    error: String,
+}
 
-#[derive(Default, Debug)]
-struct Entry {
-    address: String,
-    balance: f32,
+/// What to do with the progress of an earlier scan of the same csv file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checkpoint {
+    /// Refuse to run when an earlier scan made progress, so it isn't thrown away by accident
+    Ask,
+    /// Only check the addresses the earlier scan didn't get to
+    Resume,
+    /// Throw the earlier progress away and check every address again
+    Restart,
 }
 
-pub async fn run_csv(chain: &str, min_balance: f32, limit: usize, csv_file: &str) {
+pub async fn run_csv(
+    chain: &Chain,
+    min_balance: U256,
+    limit: usize,
+    csv_file: &str,
+    checkpoint: Checkpoint,
+) {
     //Load settings
     let setting = settings::Settings::new(chain).unwrap_or_else(|err| {
         println!(
             "Couldn't load settings file.
-            \nTry running merter --config --{} \n{}",
-            err, chain
+            \nTry running merter config --chain {} \n{}",
+            chain, err
         );
         std::process::exit(1);
     });
@@ -33,121 +74,361 @@ pub async fn run_csv(chain: &str, min_balance: f32, limit: usize, csv_file: &str
     }
 
     //Create vector of addresses above minimum treshold
-    let mut addr_vec = csv_to_vec(csv_file, min_balance).unwrap_or_else(|err| {
+    let (addr_vec, bad_rows) = csv_to_vec(csv_file, min_balance).unwrap_or_else(|err| {
         println!("Error while reading csv file: \n{}", err);
         std::process::exit(1);
     });
-    addr_vec.sort_by(|a, b| b.balance.partial_cmp(&a.balance).unwrap());
+    for row in &bad_rows {
+        println!("Skipping line {} of {}: {}", row.line, csv_file, row.error);
+    }
 
-    //Start latency timer, for rate limitting of the jsonrpc api
-    std::thread::spawn(|| timers::count_down_rpc());
+    let (path, hash) = file_key(csv_file).unwrap_or_else(|err| {
+        println!("Error while reading csv file: \n{}", err);
+        std::process::exit(1);
+    });
+    let pool = Pool::for_chain(&setting.jsonrpc, chain)
+        .await
+        .unwrap_or_else(|err| {
+            println!(
+                "Error: {} \nTry running merter config --chain {}",
+                err, chain
+            );
+            std::process::exit(1);
+        });
 
-    //Spawn tasks that check if the addresses are contracts and execute them concurrently
-    let mut tasks = FuturesUnordered::new();
-    let mut contracts: HashMap<String, bool> = HashMap::new();
+    let addr_vec = richest(addr_vec, limit);
+    scan_holders(chain, &setting, &pool, addr_vec, &path, &hash, checkpoint).await;
+}
 
-    for entry in addr_vec {
-        let url = setting.jsonrpc.url_1.clone();
-        let address = entry.address.clone();
-        let latency = setting.jsonrpc.latency_1.clone();
+/// Checks which holders are contracts and stores them. Holders are checked from high to low
+/// balance. The progress is saved under path and hash, so an interrupted scan of the same
+/// holder list can be resumed.
+pub async fn scan_holders(
+    chain: &Chain,
+    setting: &settings::Settings,
+    pool: &Pool,
+    mut holders: Vec<Entry>,
+    path: &str,
+    hash: &str,
+    checkpoint: Checkpoint,
+) {
+    holders.sort_by_key(|entry| std::cmp::Reverse(entry.balance));
+
+    let mut db = db::Db::from_settings(setting).unwrap_or_else(|err| {
+        println!("Error: Couldn't open database \n{}", err);
+        std::process::exit(1);
+    });
 
-        let container = jsonrpc::IsContractResponse {
-            address: address,
-            is_contract: false,
-            count: 0,
-        };
+    //Pick up where an earlier scan of the same holder list left off
+    let scan_id =
+        start_or_resume(&mut db, &chain.name, path, hash, checkpoint).unwrap_or_else(|err| {
+            println!("Error: Couldn't load the progress of {} \n{}", path, err);
+            std::process::exit(1);
+        });
+    let checked = db.csv_checks(scan_id).unwrap_or_else(|err| {
+        println!("Error: Couldn't load the progress of {} \n{}", path, err);
+        std::process::exit(1);
+    });
 
-        timers::push_time_rpc(latency);
-        let sleep_time = std::time::Duration::from_millis(timers::get_sleep_time_rpc() as u64);
-        tokio::time::sleep(sleep_time).await;
+    let addresses = unchecked(holders, &checked);
+    if !checked.is_empty() && addresses.is_empty() {
+        println!(
+            "Every address of {} was checked before, run again with --restart to check them again",
+            path
+        );
+    } else if !checked.is_empty() {
+        println!(
+            "Resuming, {} addresses checked before, {} left",
+            checked.len(),
+            addresses.len()
+        );
+    }
 
-        tasks.push(tokio::spawn(async move {
-            jsonrpc::is_contract(container, url).await
-        }));
+    let total = addresses.len();
+    let mut recorded = 0;
+    let found = check_contracts(pool, addresses, setting.jsonrpc.batch_size, |checks| {
+        recorded += checks.len();
+        // A stored contract without code was destroyed since it was stored
+        let checks: Vec<(Address, CodeKind)> = checks
+            .iter()
+            .map(|(address, kind)| {
+                let stored = *kind == CodeKind::Eoa
+                    && matches!(db.contract_id(&chain.name, address), Ok(Some(_)));
+                if stored {
+                    println!("{} has no code anymore, it was destroyed", address);
+                }
+                (*address, kind.since(stored))
+            })
+            .collect();
+        if let Err(e) = db.record_csv_checks(scan_id, &checks) {
+            println!("Couldn't save progress \nError: {}", e);
+        }
+    })
+    .await;
+
+    if recorded == total {
+        if let Err(e) = db.finish_csv_scan(scan_id) {
+            println!("Couldn't save progress \nError: {}", e);
+        }
+    } else {
+        println!(
+            "{} addresses couldn't be checked, run again with --resume to retry them",
+            total - recorded
+        );
     }
 
-    while let Some(finished_task) = tasks.next().await {
-        if finished_task.is_err() {
-            println!(
-                "JoinError while scanning for contract: \n{}",
-                finished_task.err().unwrap()
-            );
-            continue;
+    let earlier = checked
+        .into_iter()
+        .filter(|(_, kind)| kind.is_contract())
+        .map(|(address, _)| address);
+    for address in earlier.chain(found) {
+        println!("{}", address);
+        if let Err(e) = db.upsert_contract(&chain.name, &address) {
+            println!("Couldn't store {} \nError: {}", address, e);
         }
+    }
+}
 
-        match finished_task.unwrap() {
-            Err(e) => {
-                if e.container.count > 5 {
-                    println!(
-                        "Can't see if {} is a contract, skipping. \nError: {}",
-                        e.container.address, e.error
-                    );
-                } else {
-                    println!(
-                        "Error while checking {}, retrying. \nError: {}",
-                        e.container.address, e.error
-                    );
+/// Returns the holders from high to low balance, only the first limit of them when limit
+/// isn't 0
+fn richest(mut holders: Vec<Entry>, limit: usize) -> Vec<Entry> {
+    holders.sort_by_key(|entry| std::cmp::Reverse(entry.balance));
+    if limit != 0 {
+        holders.truncate(limit);
+    }
+    holders
+}
 
-                    let url = setting.jsonrpc.url_2.clone();
-                    let latency = setting.jsonrpc.latency_2.clone();
+/// Returns the id of the csv scan to continue. An earlier scan that made progress but didn't
+/// finish is only continued or thrown away when asked to.
+fn start_or_resume(
+    db: &mut db::Db,
+    chain: &str,
+    path: &str,
+    hash: &str,
+    checkpoint: Checkpoint,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    let earlier = match db.csv_scan(chain, path, hash)? {
+        Some(id) if checkpoint != Checkpoint::Restart => id,
+        _ => return db.start_csv_scan(chain, path, hash),
+    };
+
+    let checked = db.csv_checks(earlier)?.len();
+    if checkpoint == Checkpoint::Ask && checked > 0 && !db.csv_scan_finished(earlier)? {
+        return Err(format!(
+            "an earlier scan of {} already checked {} addresses, \
+             run again with --resume to continue it or --restart to start over",
+            path, checked
+        )
+        .into());
+    }
+    Ok(earlier)
+}
 
-                    if e.container.count % 2 == 0 {
-                        let url = setting.jsonrpc.url_1.clone();
-                        let latency = setting.jsonrpc.latency_1.clone();
-                    }
+/// Returns the addresses that weren't checked yet, without duplicates, in their original order
+fn unchecked(entries: Vec<Entry>, checked: &HashMap<Address, CodeKind>) -> Vec<Address> {
+    let mut seen = HashSet::new();
+    entries
+        .into_iter()
+        .map(|entry| entry.address)
+        .filter(|address| !checked.contains_key(address) && seen.insert(*address))
+        .collect()
+}
 
-                    timers::push_time_rpc(latency);
-                    let sleep_time =
-                        std::time::Duration::from_millis(timers::get_sleep_time_rpc() as u64);
-                    tokio::time::sleep(sleep_time).await;
+/// Returns the canonical path of a file and the keccak hash of its content, together they
+/// identify a scan
+fn file_key(csv_file: &str) -> Result<(String, String), Box<dyn Error>> {
+    let path = std::fs::canonicalize(csv_file)?;
+    let mut keccak = Keccak::v256();
+    keccak.update(&std::fs::read(&path)?);
+    let mut hash = [0u8; 32];
+    keccak.finalize(&mut hash);
+
+    let hash: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok((path.to_string_lossy().to_string(), hash))
+}
 
-                    tasks.push(tokio::spawn(async move {
-                        jsonrpc::is_contract(e.container, url).await
-                    }));
-                }
-            }
-            Ok(v) => {
-                if v.is_contract {
-                    contracts.insert(v.address, false);
+/// Returns the addresses that are contracts. The code of the addresses is requested in
+/// batches of batch_size, the batches are sent concurrently. Addresses the node fails on
+/// within a batch are retried one by one, as are all addresses of a batch the node rejects.
+/// Addresses the node still calls an invalid request on their own are recorded as invalid so
+/// they aren't asked again. Every finished batch is handed to record with the addresses that
+/// were checked and what they are.
+async fn check_contracts(
+    pool: &Pool,
+    addresses: Vec<Address>,
+    batch_size: usize,
+    mut record: impl FnMut(&[(Address, CodeKind)]),
+) -> Vec<Address> {
+    let mut tasks = FuturesUnordered::new();
+    for batch in addresses.chunks(batch_size.max(1)) {
+        let pool = pool.clone();
+        let batch = batch.to_vec();
+
+        tasks.push(tokio::spawn(async move {
+            let codes = match pool
+                .request(|url| {
+                    let batch = &batch;
+                    async move { jsonrpc::get_code_batch(batch, &url).await }
+                })
+                .await
+            {
+                Ok(codes) => codes,
+                Err(e) => {
+                    println!(
+                        "Batch of {} addresses failed, checking them one by one. \nError: {}",
+                        batch.len(),
+                        e
+                    );
+                    batch
+                        .iter()
+                        .map(|_| Err(jsonrpc::Error::Decode("batch failed".to_string())))
+                        .collect()
                 }
+            };
+
+            let mut checks = Vec::new();
+            for (address, code) in batch.into_iter().zip(codes) {
+                let code = match code {
+                    Ok(code) => code,
+                    Err(_) => match pool
+                        .request(|url| async move { jsonrpc::get_code(&address, &url).await })
+                        .await
+                    {
+                        Ok(code) => code,
+                        Err(e) if e.kind() == jsonrpc::ErrorKind::InvalidRequest => {
+                            println!("{} is invalid, skipping. \nError: {}", address, e);
+                            checks.push((address, CodeKind::Invalid));
+                            continue;
+                        }
+                        Err(e) => {
+                            println!(
+                                "Can't see if {} is a contract, skipping. \nError: {}",
+                                address, e
+                            );
+                            continue;
+                        }
+                    },
+                };
+                checks.push((address, CodeKind::classify(&address, &code)));
             }
-        }
+            checks
+        }));
     }
 
-    for (key, value) in contracts {
-        println!("{}, {}", key, value);
-    }
-    /*
-    for (ix, entry) in addr_vec.iter().enumerate() {
-        if ix > limit && limit != 0 {
-            break;
+    let mut contracts = Vec::new();
+    while let Some(finished_task) = tasks.next().await {
+        match finished_task {
+            Err(e) => println!("JoinError while scanning for contract: \n{}", e),
+            Ok(checks) => {
+                record(&checks);
+                contracts.extend(
+                    checks
+                        .into_iter()
+                        .filter(|(_, kind)| kind.is_contract())
+                        .map(|(address, _)| address),
+                );
+            }
         }
     }
-    */
+    contracts
 }
 
-fn await_is_contract_thread() {}
-
-fn csv_to_vec(csv_file: &str, min_balance: f32) -> Result<Vec<Entry>, Box<dyn Error>> {
-    let mut addr_vec: Vec<Entry> = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(csv_file)?;
-
-    for result in rdr.records() {
-        let record = result?;
-
-        let address = &record[0];
-        let balance: f32 = record[1].parse()?;
+/// Reads the holders with a balance above min_balance from a holder list. The address and
+/// balance columns are found by their header, a file without header has the address in the
+/// first and the balance in the second column. Rows that can't be read are returned with
+/// their line number instead of failing the whole file.
+fn csv_to_vec(
+    csv_file: &str,
+    min_balance: U256,
+) -> Result<(Vec<Entry>, Vec<BadRow>), Box<dyn Error>> {
+    let rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(csv_file)?;
+    Ok(read_holders(rdr, min_balance))
+}
 
-        let mut entry = Entry::default();
-        entry.balance = balance;
-        entry.address = address.to_string();
+fn read_holders<R: std::io::Read>(
+    mut rdr: csv::Reader<R>,
+    min_balance: U256,
+) -> (Vec<Entry>, Vec<BadRow>) {
+    let mut records = rdr.records().peekable();
+    // Only a first row naming both columns is a header, any other row is read as data so a
+    // malformed first row is reported like the others
+    let header = match records.peek() {
+        Some(Ok(first)) => columns(first),
+        _ => None,
+    };
+    let (address_col, balance_col) = match header {
+        Some(columns) => {
+            records.next();
+            columns
+        }
+        None => (0, 1),
+    };
+
+    let mut addr_vec = Vec::new();
+    let mut bad_rows = Vec::new();
+    for result in records {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                bad_rows.push(BadRow {
+                    line,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
 
-        if balance > min_balance {
-            addr_vec.push(entry);
+        match parse_row(&record, address_col, balance_col) {
+            Ok(entry) => {
+                if entry.balance > min_balance {
+                    addr_vec.push(entry);
+                }
+            }
+            Err(error) => bad_rows.push(BadRow { line, error }),
         }
     }
-    Ok(addr_vec)
+    (addr_vec, bad_rows)
+}
+
+/// Returns the index of the address and balance column in a header, None when the record
+/// doesn't name both
+fn columns(header: &csv::StringRecord) -> Option<(usize, usize)> {
+    let names: Vec<String> = header
+        .iter()
+        .map(|name| name.trim().to_lowercase().replace(' ', ""))
+        .collect();
+    let find = |candidates: &[&str]| {
+        candidates
+            .iter()
+            .find_map(|candidate| names.iter().position(|name| name == candidate))
+    };
+
+    Some((find(&ADDRESS_HEADERS)?, find(&BALANCE_HEADERS)?))
+}
+
+fn parse_row(
+    record: &csv::StringRecord,
+    address_col: usize,
+    balance_col: usize,
+) -> Result<Entry, String> {
+    let address = record.get(address_col).unwrap_or("").trim();
+    let address = address
+        .parse()
+        .map_err(|e| format!("invalid address {:?}, {}", address, e))?;
+    let balance = record.get(balance_col).unwrap_or("").trim();
+    let balance = units::parse_units(&balance.replace(',', ""), BALANCE_DECIMALS)
+        .map_err(|e| format!("invalid balance {:?}, {}", balance, e))?;
+
+    Ok(Entry { address, balance })
 }
 
 fn is_csv(filename: &str) -> bool {
@@ -160,24 +441,261 @@ fn is_csv(filename: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::{json, Value};
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    /// Returns the address ending in suffix, zero padded
+    fn address(suffix: &str) -> Address {
+        format!("0x{:0>40}", suffix).parse().unwrap()
+    }
+
+    /// Answers eth_getCode batches in reverse order. Addresses ending in c are contracts,
+    /// addresses ending in f fail inside a batch but succeed on their own, addresses ending in
+    /// d are refused as invalid requests inside a batch only, addresses ending in e are always
+    /// refused as invalid.
+    fn respond(request: &Request) -> ResponseTemplate {
+        let answer = |call: &Value, batched: bool| {
+            let address = call["params"][0].as_str().unwrap();
+            if batched && address.ends_with('f') {
+                json!({ "jsonrpc": "2.0", "id": call["id"], "error": { "code": -32000, "message": "busy" } })
+            } else if batched && address.ends_with('d') {
+                json!({ "jsonrpc": "2.0", "id": call["id"], "error": { "code": -32600, "message": "invalid request" } })
+            } else if address.ends_with('e') {
+                json!({ "jsonrpc": "2.0", "id": call["id"], "error": { "code": -32602, "message": "invalid argument 0" } })
+            } else {
+                let code = if address.ends_with('c') || address.ends_with('f') {
+                    "0x6080"
+                } else {
+                    "0x"
+                };
+                json!({ "jsonrpc": "2.0", "id": call["id"], "result": code })
+            }
+        };
+
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        match body.as_array() {
+            Some(calls) => {
+                let answers: Vec<Value> =
+                    calls.iter().rev().map(|call| answer(call, true)).collect();
+                ResponseTemplate::new(200).set_body_json(answers)
+            }
+            None => ResponseTemplate::new(200).set_body_json(answer(&body, false)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_contracts_batched() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(respond)
+            .mount(&server)
+            .await;
+
+        let addresses: Vec<Address> = ["1c", "2a", "3f", "4a", "5c", "6e", "7d"]
+            .iter()
+            .map(|a| address(a))
+            .collect();
+        let mut recorded = Vec::new();
+        let mut contracts = check_contracts(&Pool::single(&server.uri()), addresses, 2, |checks| {
+            recorded.extend_from_slice(checks)
+        })
+        .await;
+        contracts.sort();
+        assert_eq!(contracts, vec![address("1c"), address("3f"), address("5c")]);
+        recorded.sort_by_key(|(address, _)| *address);
+        assert_eq!(recorded.len(), 7);
+        assert_eq!(recorded[1], (address("2a"), CodeKind::Eoa));
+        // An address that's still invalid on its own counts as checked, so a resume doesn't
+        // ask for it again
+        assert_eq!(recorded[5], (address("6e"), CodeKind::Invalid));
+        // An invalid request inside a batch says nothing about the address
+        assert_eq!(recorded[6], (address("7d"), CodeKind::Eoa));
+
+        // 4 batches and the 3 failed entries on their own
+        assert_eq!(server.received_requests().await.unwrap().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_check_contracts_batches_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(|request: &Request| {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                if body.is_array() {
+                    ResponseTemplate::new(200).set_body_json(
+                        json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32600, "message": "batch not supported" } }),
+                    )
+                } else {
+                    respond(request)
+                }
+            })
+            .mount(&server)
+            .await;
+
+        let addresses = vec![address("1c"), address("2")];
+        let contracts = check_contracts(&Pool::single(&server.uri()), addresses, 10, |_| {}).await;
+        assert_eq!(contracts, vec![address("1c")]);
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut db = db::Db::open("sqlite::memory:").unwrap();
+        let first = start_or_resume(&mut db, "eth", "h.csv", "0xaa", Checkpoint::Ask).unwrap();
+        db.record_csv_checks(first, &[(address("a"), CodeKind::Contract)])
+            .unwrap();
+
+        let err = start_or_resume(&mut db, "eth", "h.csv", "0xaa", Checkpoint::Ask).unwrap_err();
+        assert!(err.to_string().contains("--resume"));
+        let resumed = start_or_resume(&mut db, "eth", "h.csv", "0xaa", Checkpoint::Resume).unwrap();
+        assert_eq!(resumed, first);
+
+        let checked = db.csv_checks(resumed).unwrap();
+        let entries = ["A", "b", "B", "c"]
+            .iter()
+            .map(|suffix| Entry {
+                address: address(suffix),
+                balance: U256::one(),
+            })
+            .collect();
+        assert_eq!(
+            unchecked(entries, &checked),
+            vec![address("b"), address("c")]
+        );
+
+        // A finished scan is continued without asking
+        db.finish_csv_scan(first).unwrap();
+        let finished = start_or_resume(&mut db, "eth", "h.csv", "0xaa", Checkpoint::Ask).unwrap();
+        assert_eq!(finished, first);
+
+        // A changed file is a new scan
+        let changed = start_or_resume(&mut db, "eth", "h.csv", "0xbb", Checkpoint::Ask).unwrap();
+        assert_ne!(changed, first);
+        let restarted =
+            start_or_resume(&mut db, "eth", "h.csv", "0xaa", Checkpoint::Restart).unwrap();
+        assert!(db.csv_checks(restarted).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_richest() {
+        let entries = || {
+            [1u64, 3, 2]
+                .iter()
+                .map(|n| Entry {
+                    address: address(&n.to_string()),
+                    balance: U256::from(*n),
+                })
+                .collect::<Vec<_>>()
+        };
+        let addresses = |entries: Vec<Entry>| -> Vec<Address> {
+            entries.into_iter().map(|entry| entry.address).collect()
+        };
+        assert_eq!(
+            addresses(richest(entries(), 2)),
+            vec![address("3"), address("2")]
+        );
+        assert_eq!(addresses(richest(entries(), 0)).len(), 3);
+    }
+
+    const HOLDER: &str = "0x1111111111111111111111111111111111111111";
+
+    fn read(csv: &str, min_balance: &str) -> (Vec<Entry>, Vec<BadRow>) {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let min_balance = units::parse_units(min_balance, BALANCE_DECIMALS).unwrap();
+        read_holders(rdr, min_balance)
+    }
+
+    #[test]
+    fn test_read_etherscan_export() {
+        let (entries, bad_rows) = read(
+            &format!(
+                "\"HolderAddress\",\"Balance\",\"PendingBalanceUpdate\"\n\
+                 \"{}\",\"1,234,567.123456789012345678\",\"No\"\n\
+                 \"0x2222222222222222222222222222222222222222\",\"0.5\",\"No\"\n",
+                HOLDER
+            ),
+            "1",
+        );
+        assert!(bad_rows.is_empty());
+        assert_eq!(
+            entries,
+            vec![Entry {
+                address: HOLDER.parse().unwrap(),
+                balance: units::parse_units("1234567.123456789012345678", BALANCE_DECIMALS)
+                    .unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_read_columns_by_header() {
+        let (entries, _) = read(&format!("Rank,Balance,Address\n1,42,{}\n", HOLDER), "0");
+        assert_eq!(entries[0].address.to_string(), HOLDER);
+
+        let (entries, _) = read(&format!("{},42\n{},7\n", HOLDER, HOLDER), "0");
+        assert_eq!(entries.len(), 2);
+
+        // A header without the columns is read as a bad row like any other
+        let (entries, bad_rows) = read("Name,Total\nfoo,1\n", "0");
+        assert!(entries.is_empty());
+        assert_eq!(bad_rows.len(), 2);
+        assert_eq!(bad_rows[0].line, 1);
+    }
+
+    #[test]
+    fn test_read_reports_bad_rows() {
+        let (entries, bad_rows) = read(
+            &format!(
+                "HolderAddress,Balance\n0x12,1\n{},lots\n\n{},3\n",
+                HOLDER, HOLDER
+            ),
+            "0",
+        );
+        assert_eq!(entries.len(), 1);
+        assert_eq!(bad_rows.len(), 2);
+        assert_eq!(bad_rows[0].line, 2);
+        assert!(bad_rows[0].error.contains("invalid address"));
+        assert_eq!(bad_rows[1].line, 3);
+        assert!(bad_rows[1].error.contains("invalid balance"));
+    }
+
+    #[test]
+    fn test_read_headerless_bad_first_row() {
+        // A wrong checksum in the first row doesn't make it a header
+        let (entries, bad_rows) = read(
+            &format!(
+                "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed,1\n{},2\n",
+                HOLDER
+            ),
+            "0",
+        );
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].address, HOLDER.parse().unwrap());
+        assert_eq!(bad_rows.len(), 1);
+        assert_eq!(bad_rows[0].line, 1);
+        assert!(bad_rows[0].error.contains("invalid address"));
+    }
 
     #[test]
     fn test_is_csv() {
-        assert_eq!(is_csv("abc.csv"), true);
+        assert!(is_csv("abc.csv"));
     }
 
     #[test]
     fn test_is_csv_long_path() {
-        assert_eq!(is_csv("/opt/c/c/d/abc.csv"), true);
+        assert!(is_csv("/opt/c/c/d/abc.csv"));
     }
 
     #[test]
     fn test_is_csv_win_path() {
-        assert_eq!(is_csv("C:DERP\\derp\\escape\\dir.csv"), true);
+        assert!(is_csv("C:DERP\\derp\\escape\\dir.csv"));
     }
 
     #[test]
     fn test_is_csv_wrong_filetype() {
-        assert_eq!(is_csv("abc.gz"), false);
+        assert!(!is_csv("abc.gz"));
     }
 }
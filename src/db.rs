@@ -0,0 +1,638 @@
+use super::address::Address;
+use super::code::CodeKind;
+use super::explorer::Metadata;
+use super::issue::Issue;
+use super::proxy::Proxy;
+use super::tokens::TokenBalance;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use primitive_types::U256;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Schema migrations, applied in order. The index + 1 is stored as user_version so every
+/// migration only runs once. Never edit a released migration, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: contracts, balances, sources, scan jobs and issues
+    "CREATE TABLE contracts (
+        id INTEGER PRIMARY KEY,
+        chain TEXT NOT NULL,
+        address TEXT NOT NULL,
+        balance_wei TEXT,
+        value_wei TEXT,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        UNIQUE (chain, address)
+    );
+    CREATE TABLE balances (
+        id INTEGER PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        asset TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        decimals INTEGER NOT NULL,
+        value_wei TEXT NOT NULL,
+        recorded_at INTEGER NOT NULL
+    );
+    CREATE TABLE sources (
+        contract_id INTEGER PRIMARY KEY REFERENCES contracts (id),
+        path TEXT NOT NULL,
+        name TEXT NOT NULL,
+        compiler_version TEXT NOT NULL,
+        optimization_used INTEGER NOT NULL,
+        runs INTEGER NOT NULL,
+        evm_version TEXT NOT NULL,
+        implementation TEXT,
+        downloaded_at INTEGER NOT NULL
+    );
+    CREATE TABLE scan_jobs (
+        id INTEGER PRIMARY KEY,
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        uuid TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        submitted_at INTEGER NOT NULL,
+        finished_at INTEGER
+    );
+    CREATE TABLE issues (
+        id INTEGER PRIMARY KEY,
+        scan_job_id INTEGER NOT NULL REFERENCES scan_jobs (id),
+        contract_id INTEGER NOT NULL REFERENCES contracts (id),
+        swc_id TEXT NOT NULL,
+        title TEXT NOT NULL,
+        severity TEXT NOT NULL,
+        description TEXT NOT NULL,
+        locations TEXT NOT NULL
+    );
+    CREATE INDEX issues_contract ON issues (contract_id);
+    CREATE INDEX issues_swc ON issues (swc_id);",
+    // 2: progress of csv scans, so an interrupted scan can be resumed
+    "CREATE TABLE csv_scans (
+        id INTEGER PRIMARY KEY,
+        chain TEXT NOT NULL,
+        path TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER,
+        UNIQUE (chain, path, hash)
+    );
+    CREATE TABLE csv_checks (
+        csv_scan_id INTEGER NOT NULL REFERENCES csv_scans (id),
+        address TEXT NOT NULL,
+        is_contract INTEGER NOT NULL,
+        checked_at INTEGER NOT NULL,
+        PRIMARY KEY (csv_scan_id, address)
+    );",
+    // 3: store what a checked address is instead of whether it's a contract
+    "ALTER TABLE csv_checks ADD COLUMN kind TEXT NOT NULL DEFAULT 'eoa';
+    UPDATE csv_checks SET kind = 'contract' WHERE is_contract;
+    ALTER TABLE csv_checks DROP COLUMN is_contract;",
+    // 4: the contract that runs the code of a proxy, and the admin and beacon of the proxy
+    "ALTER TABLE contracts ADD COLUMN implementation TEXT;
+    ALTER TABLE contracts ADD COLUMN proxy_admin TEXT;
+    ALTER TABLE contracts ADD COLUMN proxy_beacon TEXT;",
+];
+
+/// Represents the findings database
+pub struct Db {
+    conn: Connection,
+}
+
+impl Db {
+    /// Opens the database behind db_url and brings the schema up to date. The scheme picks the
+    /// backend, only sqlite is supported:
+    ///
+    /// * `sqlite:///home/user/merter.db` or `sqlite://merter.db` - database file
+    /// * `sqlite::memory:` - in-memory database
+    /// * `/home/user/merter.db` - a url without scheme is a sqlite database file
+    ///
+    pub fn open(db_url: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let conn = match db_url.split_once(':') {
+            Some(("sqlite", ":memory:")) => Connection::open_in_memory()?,
+            Some(("sqlite", path)) => Connection::open(path.trim_start_matches("//"))?,
+            Some((scheme, _)) if scheme.len() > 1 => {
+                return Err(format!(
+                    "unsupported database backend {}, use a sqlite:// url",
+                    scheme
+                )
+                .into())
+            }
+            _ => Connection::open(db_url)?,
+        };
+
+        let mut db = Db { conn };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    /// Opens the database from the settings, falls back to merter.db in the storage folder if
+    /// no db_url is set.
+    pub fn from_settings(
+        setting: &super::settings::Settings,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if setting.storage.db_url.is_empty() {
+            let path = Path::new(&setting.storage.file_path).join("merter.db");
+            return Db::open(&path.to_string_lossy());
+        }
+        Db::open(&setting.storage.db_url)
+    }
+
+    fn migrate(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let version: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (ix, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.execute_batch(&format!("PRAGMA user_version = {}", ix + 1))?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Inserts a contract or updates when it was last seen, returns its id
+    pub fn upsert_contract(
+        &self,
+        chain: &str,
+        address: &Address,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let now = now();
+        self.conn.execute(
+            "INSERT INTO contracts (chain, address, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (chain, address) DO UPDATE SET last_seen = ?3",
+            params![chain, address.to_string(), now],
+        )?;
+        Ok(self.conn.query_row(
+            "SELECT id FROM contracts WHERE chain = ?1 AND address = ?2",
+            params![chain, address.to_string()],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Stores the contract a proxy runs the code of, and who can upgrade it
+    pub fn set_proxy(
+        &self,
+        contract_id: i64,
+        proxy: &Proxy,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn.execute(
+            "UPDATE contracts SET implementation = ?1, proxy_admin = ?2, proxy_beacon = ?3
+             WHERE id = ?4",
+            params![
+                proxy.implementation.to_string(),
+                proxy.admin.map(|a| a.to_string()),
+                proxy.beacon.map(|a| a.to_string()),
+                contract_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores the native balance, held tokens and total value of a contract
+    pub fn record_balance(
+        &self,
+        contract_id: i64,
+        native_symbol: &str,
+        native_decimals: usize,
+        balance: U256,
+        value: U256,
+        tokens: &[TokenBalance],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let now = now();
+        self.conn.execute(
+            "UPDATE contracts SET balance_wei = ?1, value_wei = ?2 WHERE id = ?3",
+            params![to_sql_u256(balance), to_sql_u256(value), contract_id],
+        )?;
+
+        let mut insert = self.conn.prepare(
+            "INSERT INTO balances (contract_id, asset, amount, decimals, value_wei, recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        insert.execute(params![
+            contract_id,
+            native_symbol,
+            to_sql_u256(balance),
+            native_decimals as i64,
+            to_sql_u256(balance),
+            now
+        ])?;
+        for token in tokens {
+            insert.execute(params![
+                contract_id,
+                token.symbol,
+                to_sql_u256(token.amount),
+                token.decimals as i64,
+                to_sql_u256(token.value),
+                now
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Stores where the source code of a contract is and how it was compiled
+    pub fn save_source(
+        &self,
+        contract_id: i64,
+        dir: &Path,
+        metadata: &Metadata,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO sources (contract_id, path, name, compiler_version,
+                optimization_used, runs, evm_version, implementation, downloaded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                contract_id,
+                dir.to_string_lossy(),
+                metadata.name,
+                metadata.compiler_version,
+                metadata.optimization_used,
+                metadata.runs,
+                metadata.evm_version,
+                metadata.implementation.map(|a| a.to_string()),
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a submitted analysis, returns the id of the scan job
+    pub fn start_scan(
+        &self,
+        contract_id: i64,
+        uuid: &str,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        self.conn.execute(
+            "INSERT INTO scan_jobs (contract_id, uuid, status, submitted_at)
+             VALUES (?1, ?2, 'submitted', ?3)",
+            params![contract_id, uuid, now()],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Marks a scan job as failed
+    pub fn fail_scan(&self, job_id: i64, error: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn.execute(
+            "UPDATE scan_jobs SET status = 'failed', error = ?1, finished_at = ?2 WHERE id = ?3",
+            params![error, now(), job_id],
+        )?;
+        Ok(())
+    }
+
+    /// Marks a scan job as finished and stores the issues it found
+    pub fn finish_scan(
+        &mut self,
+        job_id: i64,
+        issues: &[Issue],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tx = self.conn.transaction()?;
+        let contract_id: i64 = tx.query_row(
+            "SELECT contract_id FROM scan_jobs WHERE id = ?1",
+            params![job_id],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "UPDATE scan_jobs SET status = 'finished', finished_at = ?1 WHERE id = ?2",
+            params![now(), job_id],
+        )?;
+        for issue in issues {
+            tx.execute(
+                "INSERT INTO issues (scan_job_id, contract_id, swc_id, title, severity,
+                    description, locations)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    job_id,
+                    contract_id,
+                    issue.swc_id,
+                    issue.title,
+                    issue.severity.to_string(),
+                    issue.description,
+                    serde_json::to_string(&issue.locations)?
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the id of the scan of a csv file, if it was scanned before. The hash is of the
+    /// file's content, so a file that changed since is a new scan.
+    pub fn csv_scan(
+        &self,
+        chain: &str,
+        path: &str,
+        hash: &str,
+    ) -> Result<Option<i64>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id FROM csv_scans WHERE chain = ?1 AND path = ?2 AND hash = ?3",
+                params![chain, path, hash],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Starts the scan of a csv file, a previous scan of the same file and its progress is
+    /// thrown away. Returns the id of the scan.
+    pub fn start_csv_scan(
+        &mut self,
+        chain: &str,
+        path: &str,
+        hash: &str,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM csv_checks WHERE csv_scan_id IN
+                (SELECT id FROM csv_scans WHERE chain = ?1 AND path = ?2 AND hash = ?3)",
+            params![chain, path, hash],
+        )?;
+        tx.execute(
+            "DELETE FROM csv_scans WHERE chain = ?1 AND path = ?2 AND hash = ?3",
+            params![chain, path, hash],
+        )?;
+        tx.execute(
+            "INSERT INTO csv_scans (chain, path, hash, started_at) VALUES (?1, ?2, ?3, ?4)",
+            params![chain, path, hash, now()],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Stores which addresses of a csv scan were checked and what they are
+    pub fn record_csv_checks(
+        &mut self,
+        csv_scan_id: i64,
+        checks: &[(Address, CodeKind)],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tx = self.conn.transaction()?;
+        let now = now();
+        for (address, kind) in checks {
+            tx.execute(
+                "INSERT OR REPLACE INTO csv_checks (csv_scan_id, address, kind, checked_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![csv_scan_id, address.to_string(), kind.to_string(), now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the addresses checked so far in a csv scan and what they are
+    pub fn csv_checks(
+        &self,
+        csv_scan_id: i64,
+    ) -> Result<HashMap<Address, CodeKind>, Box<dyn Error + Send + Sync>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT address, kind FROM csv_checks WHERE csv_scan_id = ?1")?;
+        let rows = stmt
+            .query_map(params![csv_scan_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut checks = HashMap::new();
+        for (address, kind) in rows {
+            checks.insert(address.parse()?, kind.parse()?);
+        }
+        Ok(checks)
+    }
+
+    /// Returns true when every address of a csv scan has been checked
+    pub fn csv_scan_finished(
+        &self,
+        csv_scan_id: i64,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let finished_at: Option<i64> = self.conn.query_row(
+            "SELECT finished_at FROM csv_scans WHERE id = ?1",
+            params![csv_scan_id],
+            |row| row.get(0),
+        )?;
+        Ok(finished_at.is_some())
+    }
+
+    /// Marks a csv scan as finished, every address has been checked
+    pub fn finish_csv_scan(&self, csv_scan_id: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn.execute(
+            "UPDATE csv_scans SET finished_at = ?1 WHERE id = ?2",
+            params![now(), csv_scan_id],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the id of a contract if it's stored
+    pub fn contract_id(
+        &self,
+        chain: &str,
+        address: &Address,
+    ) -> Result<Option<i64>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id FROM contracts WHERE chain = ?1 AND address = ?2",
+                params![chain, address.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+}
+
+/// Amounts are stored as zero padded decimal strings so they sort and compare correctly as
+/// text, a U256 has at most 78 digits.
+pub fn to_sql_u256(amount: U256) -> String {
+    format!("{:0>78}", amount.to_string())
+}
+
+pub fn from_sql_u256(amount: &str) -> U256 {
+    let digits = amount.trim_start_matches('0');
+    if digits.is_empty() {
+        return U256::zero();
+    }
+    U256::from_dec_str(digits).unwrap_or_default()
+}
+
+/// Returns the current unix time in seconds
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::issue::{Severity, SourceLocation};
+
+    const ADDRESS: &str = "0xAbCd000000000000000000000000000000000001";
+
+    fn address() -> Address {
+        ADDRESS.to_lowercase().parse().unwrap()
+    }
+
+    fn metadata() -> Metadata {
+        Metadata {
+            address: address(),
+            name: "Vault".to_string(),
+            compiler_version: "v0.8.4+commit.c7e474f2".to_string(),
+            optimization_used: true,
+            runs: 200,
+            evm_version: "Default".to_string(),
+            implementation: None,
+        }
+    }
+
+    fn issue() -> Issue {
+        Issue {
+            swc_id: "SWC-107".to_string(),
+            title: "Reentrancy".to_string(),
+            severity: Severity::High,
+            description: "External call".to_string(),
+            locations: vec![SourceLocation {
+                file: "Vault.sol".to_string(),
+                offset: 42,
+                length: 10,
+                line: Some(3),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_open_unsupported_backend() {
+        let err = Db::open("postgres://localhost/merter").err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("unsupported database backend postgres"));
+    }
+
+    #[test]
+    fn test_migrations_run_once() {
+        let path = std::env::temp_dir().join(format!("merter_db_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}", path.display());
+
+        let db = Db::open(&url).unwrap();
+        db.upsert_contract("eth", &address()).unwrap();
+        drop(db);
+
+        let db = Db::open(&url).unwrap();
+        let version: usize = db
+            .connection()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert!(db.contract_id("eth", &address()).unwrap().is_some());
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_upsert_contract_is_case_insensitive() {
+        let db = Db::open("sqlite::memory:").unwrap();
+        let id = db.upsert_contract("eth", &address()).unwrap();
+        let upper: Address = ADDRESS
+            .to_uppercase()
+            .replacen("0X", "0x", 1)
+            .parse()
+            .unwrap();
+        assert_eq!(db.upsert_contract("eth", &upper).unwrap(), id);
+        assert_ne!(db.upsert_contract("bsc", &address()).unwrap(), id);
+    }
+
+    #[test]
+    fn test_records() {
+        let mut db = Db::open("sqlite::memory:").unwrap();
+        let id = db.upsert_contract("eth", &address()).unwrap();
+
+        let tokens = vec![TokenBalance {
+            symbol: "USDT".to_string(),
+            amount: U256::from(4_000_000_000u64),
+            decimals: 6,
+            value: U256::exp10(18) * 2,
+        }];
+        db.record_balance(id, "ETH", 18, U256::exp10(18), U256::exp10(18) * 3, &tokens)
+            .unwrap();
+        db.save_source(id, Path::new("/tmp/eth/0xabcd"), &metadata())
+            .unwrap();
+        let job = db.start_scan(id, "uuid-1").unwrap();
+        db.finish_scan(job, &[issue()]).unwrap();
+
+        let value: String = db
+            .connection()
+            .query_row("SELECT value_wei FROM contracts WHERE id = ?1", [id], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(from_sql_u256(&value), U256::exp10(18) * 3);
+
+        let balances: i64 = db
+            .connection()
+            .query_row(
+                "SELECT COUNT(*) FROM balances WHERE contract_id = ?1",
+                [id],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(balances, 2);
+
+        let (swc, status): (String, String) = db
+            .connection()
+            .query_row(
+                "SELECT issues.swc_id, scan_jobs.status FROM issues
+                 JOIN scan_jobs ON scan_jobs.id = issues.scan_job_id",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(swc, "SWC-107");
+        assert_eq!(status, "finished");
+    }
+
+    #[test]
+    fn test_sql_u256_sorts_as_text() {
+        let small = to_sql_u256(U256::from(9));
+        let big = to_sql_u256(U256::from(10));
+        assert!(small < big);
+        assert_eq!(from_sql_u256(&to_sql_u256(U256::MAX)), U256::MAX);
+        assert_eq!(from_sql_u256(&to_sql_u256(U256::zero())), U256::zero());
+    }
+
+    #[test]
+    fn test_csv_scan_progress() {
+        let mut db = Db::open("sqlite::memory:").unwrap();
+        assert_eq!(db.csv_scan("eth", "holders.csv", "0xaa").unwrap(), None);
+
+        let id = db.start_csv_scan("eth", "holders.csv", "0xaa").unwrap();
+        db.record_csv_checks(
+            id,
+            &[
+                (address(), CodeKind::Contract),
+                (Address::zero(), CodeKind::Eoa),
+            ],
+        )
+        .unwrap();
+        assert_eq!(db.csv_scan("eth", "holders.csv", "0xaa").unwrap(), Some(id));
+        assert_eq!(db.csv_scan("eth", "holders.csv", "0xbb").unwrap(), None);
+        assert_eq!(db.csv_scan("bsc", "holders.csv", "0xaa").unwrap(), None);
+
+        let checks = db.csv_checks(id).unwrap();
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[&address()], CodeKind::Contract);
+        assert!(!db.csv_scan_finished(id).unwrap());
+        db.finish_csv_scan(id).unwrap();
+        assert!(db.csv_scan_finished(id).unwrap());
+
+        // Restarting throws the progress away
+        let restarted = db.start_csv_scan("eth", "holders.csv", "0xaa").unwrap();
+        assert!(db.csv_checks(restarted).unwrap().is_empty());
+        assert!(db.csv_checks(id).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,444 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::ratelimit::RateLimiter;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Represents a response of the etherscan/bscscan api, on errors the result is a message
+#[derive(Debug, Deserialize)]
+struct ScanResponse {
+    status: String,
+    message: String,
+    result: serde_json::Value,
+}
+
+/// Represents an entry of the contract/getsourcecode endpoint
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SourceCodeEntry {
+    source_code: String,
+    #[serde(rename = "ABI")]
+    abi: String,
+    contract_name: String,
+    compiler_version: String,
+    optimization_used: String,
+    runs: String,
+    #[serde(default, rename = "EVMVersion")]
+    evm_version: String,
+    #[serde(default)]
+    proxy: String,
+    #[serde(default)]
+    implementation: String,
+}
+
+/// Represents the "standard json input" format of solc, only the parts merter uses
+#[derive(Debug, Deserialize)]
+struct StandardJson {
+    sources: BTreeMap<String, SourceFile>,
+    #[serde(default)]
+    settings: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourceFile {
+    content: String,
+}
+
+/// Represents the compiler settings of a verified contract, written to metadata.json
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Metadata {
+    pub address: Address,
+    pub name: String,
+    pub compiler_version: String,
+    pub optimization_used: bool,
+    pub runs: u32,
+    pub evm_version: String,
+    pub implementation: Option<Address>,
+}
+
+/// Represents the verified source code of a contract
+#[derive(Debug)]
+pub struct ContractSource {
+    pub metadata: Metadata,
+    pub abi: String,
+    pub files: Vec<(PathBuf, String)>,
+}
+
+/// Client for the etherscan compatible api of a block explorer, e.g. etherscan or bscscan
+pub struct Explorer {
+    client: reqwest::Client,
+    api_url: String,
+    key: String,
+    limiter: RateLimiter,
+}
+
+impl Explorer {
+    pub fn new(api_url: &str, key: &str) -> Self {
+        Explorer {
+            client: reqwest::Client::new(),
+            api_url: api_url.to_string(),
+            key: key.to_string(),
+            limiter: RateLimiter::unlimited(),
+        }
+    }
+
+    /// Sends every request through limiter
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Creates a client for the explorer of the selected chain
+    pub fn for_chain(chain: &Chain, key: &str) -> Self {
+        Explorer::new(&chain.explorer_api, key)
+    }
+
+    /// Grabs the verified source code of a contract. Returns None if the contract isn't
+    /// verified.
+    pub async fn get_source_code(
+        &self,
+        address: &Address,
+    ) -> Result<Option<ContractSource>, Box<dyn Error + Send + Sync>> {
+        self.limiter.acquire().await;
+        let response: ScanResponse = self
+            .client
+            .get(&self.api_url)
+            .query(&[
+                ("module", "contract"),
+                ("action", "getsourcecode"),
+                ("address", &address.to_string()),
+                ("apikey", &self.key),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if response.status != "1" {
+            return Err(format!("{}: {}", response.message, response.result).into());
+        }
+
+        let mut entries: Vec<SourceCodeEntry> = serde_json::from_value(response.result)?;
+        if entries.is_empty() {
+            return Err("empty result".into());
+        }
+        let entry = entries.remove(0);
+
+        if entry.source_code.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(parse_entry(address, entry)?))
+    }
+
+    /// Grabs the source code of a contract and writes it to disk. If the contract is a proxy
+    /// the source code of the implementation is downloaded as well. Returns the directories
+    /// that were written, empty if the contract isn't verified.
+    pub async fn download(
+        &self,
+        root: &Path,
+        chain: &str,
+        address: &Address,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+        let mut written = Vec::new();
+
+        let source = match self.get_source_code(address).await? {
+            Some(source) => source,
+            None => return Ok(written),
+        };
+        written.push(write_source(root, chain, &source)?);
+
+        if let Some(implementation) = &source.metadata.implementation {
+            if let Some(source) = self.get_source_code(implementation).await? {
+                written.push(write_source(root, chain, &source)?);
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+/// Turns an entry of the api into source files, handling single file, multi file and
+/// standard json input responses.
+fn parse_entry(
+    address: &Address,
+    entry: SourceCodeEntry,
+) -> Result<ContractSource, Box<dyn Error + Send + Sync>> {
+    let mut metadata = Metadata {
+        address: *address,
+        name: entry.contract_name.clone(),
+        compiler_version: entry.compiler_version.clone(),
+        optimization_used: entry.optimization_used == "1",
+        runs: entry.runs.parse().unwrap_or(0),
+        evm_version: entry.evm_version.clone(),
+        implementation: None,
+    };
+    if entry.proxy == "1" {
+        metadata.implementation = entry.implementation.parse().ok();
+    }
+
+    let code = entry.source_code.trim();
+    let files = if code.starts_with("{{") && code.ends_with("}}") {
+        // Standard json input is wrapped in an extra pair of braces
+        let input: StandardJson = serde_json::from_str(&code[1..code.len() - 1])?;
+        if let Some(optimizer) = input.settings.get("optimizer") {
+            metadata.optimization_used = optimizer["enabled"].as_bool().unwrap_or(false);
+            metadata.runs = optimizer["runs"].as_u64().unwrap_or(0) as u32;
+        }
+        to_files(input.sources)
+    } else if code.starts_with('{') {
+        let sources: BTreeMap<String, SourceFile> = serde_json::from_str(code)?;
+        to_files(sources)
+    } else {
+        vec![(
+            sanitize_path(&format!("{}.sol", entry.contract_name)),
+            entry.source_code,
+        )]
+    };
+
+    Ok(ContractSource {
+        metadata,
+        abi: entry.abi,
+        files,
+    })
+}
+
+fn to_files(sources: BTreeMap<String, SourceFile>) -> Vec<(PathBuf, String)> {
+    sources
+        .into_iter()
+        .map(|(path, file)| (sanitize_path(&path), file.content))
+        .collect()
+}
+
+/// Keeps a source path inside the contract directory by dropping root and parent components
+fn sanitize_path(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes the source files, abi.json and metadata.json to file_path/chain/address/ and
+/// returns the directory.
+pub fn write_source(
+    root: &Path,
+    chain: &str,
+    source: &ContractSource,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let dir = root.join(chain).join(source.metadata.address.to_string());
+    std::fs::create_dir_all(&dir)?;
+
+    for (path, content) in &source.files {
+        let file = dir.join(path);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(file, content)?;
+    }
+
+    std::fs::write(dir.join("abi.json"), &source.abi)?;
+    std::fs::write(
+        dir.join("metadata.json"),
+        serde_json::to_string_pretty(&source.metadata)?,
+    )?;
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SINGLE: &str = "0x1111111111111111111111111111111111111111";
+    const PROXY: &str = "0x2222222222222222222222222222222222222222";
+    const IMPL: &str = "0x3333333333333333333333333333333333333333";
+    const UNVERIFIED: &str = "0x4444444444444444444444444444444444444444";
+
+    fn address(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
+    fn entry(
+        source_code: &str,
+        name: &str,
+        proxy: &str,
+        implementation: &str,
+    ) -> serde_json::Value {
+        json!({
+            "status": "1",
+            "message": "OK",
+            "result": [{
+                "SourceCode": source_code,
+                "ABI": "[]",
+                "ContractName": name,
+                "CompilerVersion": "v0.8.4+commit.c7e474f2",
+                "OptimizationUsed": "1",
+                "Runs": "200",
+                "ConstructorArguments": "",
+                "EVMVersion": "Default",
+                "Library": "",
+                "LicenseType": "MIT",
+                "Proxy": proxy,
+                "Implementation": implementation,
+                "SwarmSource": "",
+            }]
+        })
+    }
+
+    async fn mock_explorer() -> MockServer {
+        let server = MockServer::start().await;
+        let mock = |address: &str, body: serde_json::Value| {
+            Mock::given(method("GET"))
+                .and(query_param("module", "contract"))
+                .and(query_param("action", "getsourcecode"))
+                .and(query_param("address", address))
+                .and(query_param("apikey", "KEY"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        };
+
+        mock(SINGLE, entry("contract Single {}", "Single", "0", ""))
+            .mount(&server)
+            .await;
+
+        let standard_json = json!({
+            "language": "Solidity",
+            "sources": {
+                "contracts/Proxy.sol": { "content": "contract Proxy {}" },
+                "/abs/../lib/Lib.sol": { "content": "library Lib {}" },
+            },
+            "settings": { "optimizer": { "enabled": false, "runs": 999 } },
+        });
+        mock(
+            PROXY,
+            entry(&format!("{{{}}}", standard_json), "Proxy", "1", IMPL),
+        )
+        .mount(&server)
+        .await;
+
+        let multi_file = json!({
+            "Impl.sol": { "content": "contract Impl {}" },
+            "IERC20.sol": { "content": "interface IERC20 {}" },
+        });
+        mock(IMPL, entry(&multi_file.to_string(), "Impl", "0", ""))
+            .mount(&server)
+            .await;
+
+        mock(UNVERIFIED, entry("", "", "0", ""))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("merter_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(
+            sanitize_path("/abs/../lib/Lib.sol"),
+            PathBuf::from("abs/lib/Lib.sol")
+        );
+        assert_eq!(
+            sanitize_path("../../etc/passwd"),
+            PathBuf::from("etc/passwd")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_file() {
+        let server = mock_explorer().await;
+        let root = temp_root("single");
+        let explorer = Explorer::new(&server.uri(), "KEY");
+
+        let dirs = explorer
+            .download(&root, "eth", &address(SINGLE))
+            .await
+            .unwrap();
+        assert_eq!(dirs, vec![root.join("eth").join(SINGLE)]);
+        assert_eq!(
+            std::fs::read_to_string(dirs[0].join("Single.sol")).unwrap(),
+            "contract Single {}"
+        );
+
+        let metadata: Metadata =
+            serde_json::from_str(&std::fs::read_to_string(dirs[0].join("metadata.json")).unwrap())
+                .unwrap();
+        assert_eq!(metadata.compiler_version, "v0.8.4+commit.c7e474f2");
+        assert!(metadata.optimization_used);
+        assert_eq!(metadata.runs, 200);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_with_standard_json_and_multi_file_implementation() {
+        let server = mock_explorer().await;
+        let root = temp_root("proxy");
+        let explorer = Explorer::new(&server.uri(), "KEY");
+
+        let dirs = explorer
+            .download(&root, "bsc", &address(PROXY))
+            .await
+            .unwrap();
+        assert_eq!(
+            dirs,
+            vec![root.join("bsc").join(PROXY), root.join("bsc").join(IMPL)]
+        );
+
+        assert!(dirs[0].join("contracts/Proxy.sol").exists());
+        assert!(dirs[0].join("abs/lib/Lib.sol").exists());
+        let metadata: Metadata =
+            serde_json::from_str(&std::fs::read_to_string(dirs[0].join("metadata.json")).unwrap())
+                .unwrap();
+        assert!(!metadata.optimization_used);
+        assert_eq!(metadata.runs, 999);
+        assert_eq!(metadata.implementation, Some(address(IMPL)));
+
+        assert!(dirs[1].join("Impl.sol").exists());
+        assert!(dirs[1].join("IERC20.sol").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unverified() {
+        let server = mock_explorer().await;
+        let explorer = Explorer::new(&server.uri(), "KEY");
+        assert!(explorer
+            .get_source_code(&address(UNVERIFIED))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_api_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": "0",
+                "message": "NOTOK",
+                "result": "Invalid API Key",
+            })))
+            .mount(&server)
+            .await;
+        let explorer = Explorer::new(&server.uri(), "BAD");
+
+        let err = explorer
+            .get_source_code(&address(SINGLE))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid API Key"));
+    }
+}
@@ -0,0 +1,409 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::code::CodeKind;
+use super::jsonrpc;
+use super::pipeline;
+use super::pool::Pool;
+use super::ratelimit::Limiters;
+use super::settings;
+use super::tokens;
+use super::units;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use primitive_types::U256;
+
+/// Represents a contract found in the latest block together with what kind of contract it
+/// is, its balance in wei, the tokens it holds and the total value of both in wei
+#[derive(Debug, PartialEq)]
+pub struct Contract {
+    pub address: Address,
+    pub kind: CodeKind,
+    pub balance: U256,
+    pub tokens: Vec<tokens::TokenBalance>,
+    pub value: U256,
+}
+
+/// Prints a contract, its value in the native coin with decimals and the tokens it holds
+pub fn print_contract(contract: &Contract, decimals: usize) {
+    println!(
+        "{}, {}",
+        contract.address.checksum(),
+        units::format_units(contract.value, decimals)
+    );
+    if let CodeKind::MinimalProxy(implementation) = contract.kind {
+        println!("    minimal proxy of {}", implementation.checksum());
+    }
+    for token in &contract.tokens {
+        println!(
+            "    {} {}",
+            units::format_units(token.amount, token.decimals),
+            token.symbol
+        );
+    }
+}
+
+pub async fn run_find(chain: &Chain, min_balance: U256, limit: usize) {
+    //Load settings
+    let setting = settings::Settings::new(chain).unwrap_or_else(|err| {
+        println!(
+            "Couldn't load settings file.
+            \nTry running merter config --chain {} \n{}",
+            chain, err
+        );
+        std::process::exit(1);
+    });
+
+    let pool = Pool::for_chain(&setting.jsonrpc, chain)
+        .await
+        .unwrap_or_else(|err| {
+            println!(
+                "Error: {} \nTry running merter config --chain {}",
+                err, chain
+            );
+            std::process::exit(1);
+        });
+    let limiters = Limiters::from_settings(&setting);
+    let token_list = tokens::resolve(
+        &pool,
+        tokens::for_chain(chain, &setting.tokens),
+        chain.decimals,
+    )
+    .await;
+
+    let contracts = find_contracts(&pool, &token_list, min_balance, limit)
+        .await
+        .unwrap_or_else(|err| {
+            println!("Error while grabbing the latest block: \n{}", err);
+            std::process::exit(1);
+        });
+
+    for contract in &contracts {
+        print_contract(contract, chain.decimals);
+    }
+
+    let mut pipeline = pipeline::Pipeline::new(&setting, chain, &pool, &limiters, limit).await;
+    for contract in &contracts {
+        pipeline.process(contract).await;
+    }
+}
+
+/// Grabs the latest block, checks every unique from/to address and returns the contracts
+/// with a value above min_balance, sorted from high to low value.
+///
+/// # Arguments
+///
+/// * `pool` - The json-rpc endpoints
+/// * `token_list` - Tokens of which the balance counts towards the value of a contract
+/// * `min_balance` - Minimum value in wei
+/// * `limit` - Maximum amount of contracts returned, 0 means no limit
+///
+pub async fn find_contracts(
+    pool: &Pool,
+    token_list: &[tokens::ResolvedToken],
+    min_balance: U256,
+    limit: usize,
+) -> Result<Vec<Contract>, Box<dyn Error>> {
+    let block = pool
+        .request(|url| async move { jsonrpc::get_latest_block(&url).await })
+        .await?;
+    Ok(block_contracts(pool, token_list, &block, min_balance, limit).await)
+}
+
+/// Checks every unique from/to address of a block, and the contracts it created, and returns
+/// the contracts with a value above min_balance, ranked from high to low value. The value is
+/// the native balance plus the value of the held tokens. Addresses that fail to be checked
+/// are skipped.
+///
+/// The ranking is done before any code is downloaded or scanned, so the limit always
+/// keeps the most valuable contracts.
+pub async fn block_contracts(
+    pool: &Pool,
+    token_list: &[tokens::ResolvedToken],
+    block: &jsonrpc::EthTransactions,
+    min_balance: U256,
+    limit: usize,
+) -> Vec<Contract> {
+    let mut addresses = block_addresses(block);
+    let created = created_contracts(pool, block).await;
+    println!(
+        "Block {} downloaded, {} txs, {} unique addresses, {} contracts created",
+        block.number.as_deref().unwrap_or("latest"),
+        block.transactions.len(),
+        addresses.len(),
+        created.len()
+    );
+    addresses.extend(created);
+
+    //Check which addresses are contracts
+    let mut tasks = FuturesUnordered::new();
+    for address in addresses {
+        let pool = pool.clone();
+        tasks.push(tokio::spawn(async move {
+            let code = pool
+                .request(|url| async move { jsonrpc::get_code(&address, &url).await })
+                .await;
+            (address, code)
+        }));
+    }
+
+    let mut contract_addresses = Vec::new();
+    while let Some(finished_task) = tasks.next().await {
+        match finished_task {
+            Err(e) => println!("JoinError while scanning for contract: \n{}", e),
+            Ok((address, Err(e))) => println!(
+                "Can't see if {} is a contract, skipping. \nError: {}",
+                address, e
+            ),
+            Ok((address, Ok(code))) => {
+                let kind = CodeKind::classify(&address, &code);
+                if kind.is_contract() {
+                    contract_addresses.push((address, kind));
+                }
+            }
+        }
+    }
+
+    //Grab the balance and token holdings of every contract
+    let token_list = Arc::new(token_list.to_vec());
+    let mut tasks = FuturesUnordered::new();
+    for (address, kind) in contract_addresses {
+        let pool = pool.clone();
+        let token_list = token_list.clone();
+
+        tasks.push(tokio::spawn(async move {
+            let balance = match pool
+                .request(|url| async move { jsonrpc::get_balance(&address, &url).await })
+                .await
+            {
+                Ok(balance) => balance,
+                Err(e) => return (address, kind, Err(e)),
+            };
+            let held = tokens::holdings(&pool, &address, &token_list).await;
+            (address, kind, Ok((balance, held)))
+        }));
+    }
+
+    let mut contracts = Vec::new();
+    while let Some(finished_task) = tasks.next().await {
+        match finished_task {
+            Err(e) => println!("JoinError while grabbing balance: \n{}", e),
+            Ok((address, _, Err(e))) => println!(
+                "Couldn't grab balance of {}, skipping. \nError: {}",
+                address, e
+            ),
+            Ok((address, kind, Ok((balance, held)))) => {
+                let value = tokens::total_value(balance, &held);
+                if value >= min_balance {
+                    contracts.push(Contract {
+                        address,
+                        kind,
+                        balance,
+                        tokens: held,
+                        value,
+                    });
+                }
+            }
+        }
+    }
+
+    rank_by_value(&mut contracts, limit);
+    contracts
+}
+
+/// Sorts contracts from high to low value and keeps the first `limit`, 0 means no limit.
+pub fn rank_by_value(contracts: &mut Vec<Contract>, limit: usize) {
+    contracts.sort_by_key(|c| std::cmp::Reverse(c.value));
+    if limit != 0 {
+        contracts.truncate(limit);
+    }
+}
+
+/// Returns every unique from and to address of the transactions in a block
+fn block_addresses(block: &jsonrpc::EthTransactions) -> HashSet<Address> {
+    let mut addresses = HashSet::new();
+    for tx in &block.transactions {
+        addresses.insert(tx.from);
+        addresses.extend(tx.to);
+    }
+    addresses
+}
+
+/// Returns the addresses of the contracts created in a block. Contract creations have no to
+/// address, the new address is in the receipt. Receipts that fail are skipped.
+async fn created_contracts(pool: &Pool, block: &jsonrpc::EthTransactions) -> Vec<Address> {
+    let mut created = Vec::new();
+    let creations = block
+        .transactions
+        .iter()
+        .filter(|tx| tx.to.is_none())
+        .filter_map(|tx| tx.hash.as_deref());
+    for hash in creations {
+        let receipt = pool
+            .request(|url| async move { jsonrpc::get_transaction_receipt(hash, &url).await })
+            .await;
+        match receipt {
+            Ok(receipt) => created.extend(receipt.contract_address),
+            Err(e) => println!(
+                "Couldn't grab receipt of contract creation {}, skipping. \nError: {}",
+                hash, e
+            ),
+        }
+    }
+    created
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const WALLET: &str = "0x1111111111111111111111111111111111111111";
+    const RICH: &str = "0x2222222222222222222222222222222222222222";
+    const POOR: &str = "0x3333333333333333333333333333333333333333";
+    const CREATED: &str = "0x4444444444444444444444444444444444444444";
+
+    async fn mock_rpc(
+        server: &MockServer,
+        rpc_method: &str,
+        params: serde_json::Value,
+        result: serde_json::Value,
+    ) {
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                json!({ "method": rpc_method, "params": params }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": result,
+            })))
+            .mount(server)
+            .await;
+    }
+
+    async fn mock_node() -> MockServer {
+        let server = MockServer::start().await;
+        mock_rpc(
+            &server,
+            "eth_getBlockByNumber",
+            json!(["latest", true]),
+            json!({ "transactions": [
+                { "from": WALLET, "to": RICH },
+                { "from": WALLET, "to": POOR },
+                { "from": WALLET, "to": RICH },
+                { "from": WALLET, "to": null, "hash": "0xabc" },
+            ]}),
+        )
+        .await;
+        mock_rpc(
+            &server,
+            "eth_getTransactionReceipt",
+            json!(["0xabc"]),
+            json!({ "transactionHash": "0xabc", "contractAddress": CREATED, "to": null }),
+        )
+        .await;
+        mock_rpc(&server, "eth_getCode", json!([WALLET]), json!("0x")).await;
+        mock_rpc(&server, "eth_getCode", json!([RICH]), json!("0x6080")).await;
+        mock_rpc(&server, "eth_getCode", json!([POOR]), json!("0x6080")).await;
+        mock_rpc(&server, "eth_getCode", json!([CREATED]), json!("0x6080")).await;
+        // 5, 0.5 and 0.1 eth
+        mock_rpc(
+            &server,
+            "eth_getBalance",
+            json!([RICH]),
+            json!("0x4563918244f40000"),
+        )
+        .await;
+        mock_rpc(
+            &server,
+            "eth_getBalance",
+            json!([POOR]),
+            json!("0x6f05b59d3b20000"),
+        )
+        .await;
+        mock_rpc(
+            &server,
+            "eth_getBalance",
+            json!([CREATED]),
+            json!("0x16345785d8a0000"),
+        )
+        .await;
+        server
+    }
+
+    #[test]
+    fn test_block_addresses_dedupes() {
+        let block: jsonrpc::EthTransactions = serde_json::from_value(json!({ "transactions": [
+            { "from": WALLET, "to": RICH },
+            { "from": WALLET, "to": RICH.to_uppercase().replacen("0X", "0x", 1) },
+            { "from": WALLET, "to": null },
+        ]}))
+        .unwrap();
+        assert_eq!(block_addresses(&block).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_find_contracts() {
+        let server = mock_node().await;
+        let contracts = find_contracts(&Pool::single(&server.uri()), &[], U256::zero(), 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            contracts,
+            vec![
+                Contract {
+                    address: RICH.parse().unwrap(),
+                    kind: CodeKind::Contract,
+                    balance: units::parse_ether("5").unwrap(),
+                    tokens: vec![],
+                    value: units::parse_ether("5").unwrap(),
+                },
+                Contract {
+                    address: POOR.parse().unwrap(),
+                    kind: CodeKind::Contract,
+                    balance: units::parse_ether("0.5").unwrap(),
+                    tokens: vec![],
+                    value: units::parse_ether("0.5").unwrap(),
+                },
+                Contract {
+                    address: CREATED.parse().unwrap(),
+                    kind: CodeKind::Contract,
+                    balance: units::parse_ether("0.1").unwrap(),
+                    tokens: vec![],
+                    value: units::parse_ether("0.1").unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_contracts_min_balance() {
+        let server = mock_node().await;
+        let contracts = find_contracts(
+            &Pool::single(&server.uri()),
+            &[],
+            units::parse_ether("1").unwrap(),
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[0].address.to_string(), RICH);
+    }
+
+    #[tokio::test]
+    async fn test_find_contracts_limit() {
+        let server = mock_node().await;
+        let contracts = find_contracts(&Pool::single(&server.uri()), &[], U256::zero(), 1)
+            .await
+            .unwrap();
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[0].address.to_string(), RICH);
+    }
+}
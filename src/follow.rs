@@ -0,0 +1,349 @@
+use super::chain::Chain;
+use super::find;
+use super::jsonrpc;
+use super::pipeline;
+use super::pool::Pool;
+use super::ratelimit::Limiters;
+use super::settings;
+use super::tokens;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+/// Time between polls for a new head
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Amount of processed block hashes kept to detect and recover from reorgs
+pub const REORG_DEPTH: usize = 12;
+
+/// Represents a processed block
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProcessedBlock {
+    pub number: u64,
+    pub hash: String,
+}
+
+/// Represents the position of the follower, saved to disk after every processed block
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Cursor {
+    pub last_block: Option<u64>,
+    pub recent: VecDeque<ProcessedBlock>,
+}
+
+impl Cursor {
+    /// Loads the cursor from disk, returns an empty cursor if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if !path.exists() {
+            return Ok(Cursor::default());
+        }
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Tails the chain from the saved cursor, making shure every block between the last processed
+/// block and the head is handed out exactly once, unless a reorg replaced it.
+pub struct Follower {
+    pool: Pool,
+    cursor: Cursor,
+    cursor_path: PathBuf,
+}
+
+impl Follower {
+    /// Creates a follower, picking up from the cursor file if there is one.
+    pub fn new(pool: &Pool, cursor_path: PathBuf) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Follower {
+            pool: pool.clone(),
+            cursor: Cursor::load(&cursor_path)?,
+            cursor_path,
+        })
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    /// Returns the next block that hasn't been processed, or None when the follower is at the
+    /// head. If the block doesn't build on the last processed block the follower rewinds to
+    /// the last block that's still canonical and hands out the replaced blocks again.
+    pub async fn next_block(
+        &mut self,
+    ) -> Result<Option<jsonrpc::EthTransactions>, Box<dyn Error + Send + Sync>> {
+        let head = self
+            .pool
+            .request(|url| async move { jsonrpc::get_block_number(&url).await })
+            .await?;
+
+        loop {
+            let next = match self.cursor.last_block {
+                Some(n) => n + 1,
+                None => head,
+            };
+            if next > head {
+                return Ok(None);
+            }
+
+            let block = self.get_block(next).await?;
+
+            match self.cursor.recent.back() {
+                Some(prev)
+                    if prev.number + 1 == next
+                        && block.parent_hash.as_ref() != Some(&prev.hash) =>
+                {
+                    println!("Reorg detected at block {}, rewinding", next);
+                    self.rewind().await?;
+                }
+                _ => return Ok(Some(block)),
+            }
+        }
+    }
+
+    /// Marks a block as processed and saves the cursor.
+    pub fn mark_processed(
+        &mut self,
+        block: &jsonrpc::EthTransactions,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let number = match &block.number {
+            Some(n) => jsonrpc::parse_hex_u64(n)?,
+            None => return Err("block without number".into()),
+        };
+
+        self.cursor.last_block = Some(number);
+        self.cursor.recent.push_back(ProcessedBlock {
+            number,
+            hash: block.hash.clone().unwrap_or_default(),
+        });
+        while self.cursor.recent.len() > REORG_DEPTH {
+            self.cursor.recent.pop_front();
+        }
+
+        self.cursor.save(&self.cursor_path)
+    }
+
+    async fn get_block(
+        &self,
+        number: u64,
+    ) -> Result<jsonrpc::EthTransactions, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .pool
+            .request(|url| async move { jsonrpc::get_block_by_number(&url, number).await })
+            .await?)
+    }
+
+    /// Re-checks the recent blocks from new to old and drops the ones that were replaced.
+    /// If none of them are canonical anymore, it restarts from the oldest one.
+    async fn rewind(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        while let Some(prev) = self.cursor.recent.back().cloned() {
+            let block = self.get_block(prev.number).await?;
+            if block.hash.as_ref() == Some(&prev.hash) {
+                self.cursor.last_block = Some(prev.number);
+                return self.cursor.save(&self.cursor_path);
+            }
+
+            self.cursor.recent.pop_back();
+            self.cursor.last_block = prev.number.checked_sub(1);
+        }
+
+        println!(
+            "Reorg deeper than {} blocks, continuing from block {:?}",
+            REORG_DEPTH, self.cursor.last_block
+        );
+        self.cursor.save(&self.cursor_path)
+    }
+}
+
+/// Follows the chain and runs every new block through find mode. Runs until killed.
+pub async fn run_follow(chain: &Chain, min_balance: U256, limit: usize) {
+    //Load settings
+    let setting = settings::Settings::new(chain).unwrap_or_else(|err| {
+        println!(
+            "Couldn't load settings file.
+            \nTry running merter config --chain {} \n{}",
+            chain, err
+        );
+        std::process::exit(1);
+    });
+
+    let cursor_path = Path::new(&setting.storage.file_path).join(format!("{}_cursor.json", chain));
+    let pool = Pool::for_chain(&setting.jsonrpc, chain)
+        .await
+        .unwrap_or_else(|err| {
+            println!(
+                "Error: {} \nTry running merter config --chain {}",
+                err, chain
+            );
+            std::process::exit(1);
+        });
+    let limiters = Limiters::from_settings(&setting);
+    let mut follower = Follower::new(&pool, cursor_path).unwrap_or_else(|err| {
+        println!("Error: Couldn't load cursor file \n{}", err);
+        std::process::exit(1);
+    });
+
+    if let Some(n) = follower.cursor().last_block {
+        println!("Resuming after block {}", n);
+    }
+
+    let token_list = tokens::resolve(
+        &pool,
+        tokens::for_chain(chain, &setting.tokens),
+        chain.decimals,
+    )
+    .await;
+    let mut pipeline = pipeline::Pipeline::new(&setting, chain, &pool, &limiters, limit).await;
+
+    loop {
+        match follower.next_block().await {
+            Ok(Some(block)) => {
+                let contracts =
+                    find::block_contracts(&pool, &token_list, &block, min_balance, limit).await;
+                for contract in &contracts {
+                    find::print_contract(contract, chain.decimals);
+                }
+                for contract in &contracts {
+                    pipeline.process(contract).await;
+                }
+
+                if let Err(e) = follower.mark_processed(&block) {
+                    println!("Error: Couldn't save cursor \n{}", e);
+                    std::process::exit(1);
+                }
+            }
+            Ok(None) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(e) => {
+                println!("Error while following the chain, retrying. \nError: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn mock_rpc(server: &MockServer, body: serde_json::Value, result: serde_json::Value) {
+        Mock::given(method("POST"))
+            .and(body_partial_json(body))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": result,
+            })))
+            .mount(server)
+            .await;
+    }
+
+    /// Serves blocks 1 up to 3, block n has hash 0xbn and builds on 0xb(n-1)
+    async fn mock_chain() -> MockServer {
+        let server = MockServer::start().await;
+        mock_rpc(
+            &server,
+            json!({ "method": "eth_blockNumber" }),
+            json!("0x3"),
+        )
+        .await;
+        for n in 1..=3 {
+            mock_rpc(
+                &server,
+                json!({ "method": "eth_getBlockByNumber", "params": [format!("{:#x}", n)] }),
+                json!({
+                    "number": format!("{:#x}", n),
+                    "hash": format!("0xb{}", n),
+                    "parentHash": format!("0xb{}", n - 1),
+                    "transactions": [],
+                }),
+            )
+            .await;
+        }
+        server
+    }
+
+    fn cursor_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("merter_{}_{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn cursor_at(path: &Path, blocks: &[(u64, &str)]) {
+        let cursor = Cursor {
+            last_block: blocks.last().map(|b| b.0),
+            recent: blocks
+                .iter()
+                .map(|b| ProcessedBlock {
+                    number: b.0,
+                    hash: b.1.to_string(),
+                })
+                .collect(),
+        };
+        cursor.save(path).unwrap();
+    }
+
+    async fn drain(follower: &mut Follower) -> Vec<String> {
+        let mut seen = Vec::new();
+        while let Some(block) = follower.next_block().await.unwrap() {
+            seen.push(block.number.clone().unwrap());
+            follower.mark_processed(&block).unwrap();
+        }
+        seen
+    }
+
+    #[tokio::test]
+    async fn test_starts_at_head() {
+        let server = mock_chain().await;
+        let path = cursor_path("head");
+        let mut follower = Follower::new(&Pool::single(&server.uri()), path.clone()).unwrap();
+
+        assert_eq!(drain(&mut follower).await, vec!["0x3"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetches_every_block_since_cursor() {
+        let server = mock_chain().await;
+        let path = cursor_path("gap");
+        cursor_at(&path, &[(1, "0xb1")]);
+        let mut follower = Follower::new(&Pool::single(&server.uri()), path.clone()).unwrap();
+
+        assert_eq!(drain(&mut follower).await, vec!["0x2", "0x3"]);
+
+        // A restart picks up from the saved cursor
+        let mut follower = Follower::new(&Pool::single(&server.uri()), path.clone()).unwrap();
+        assert_eq!(follower.cursor().last_block, Some(3));
+        assert!(drain(&mut follower).await.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reorg_rewinds_to_canonical_block() {
+        let server = mock_chain().await;
+        let path = cursor_path("reorg");
+        cursor_at(&path, &[(1, "0xb1"), (2, "0xstale")]);
+        let mut follower = Follower::new(&Pool::single(&server.uri()), path.clone()).unwrap();
+
+        assert_eq!(drain(&mut follower).await, vec!["0x2", "0x3"]);
+        assert_eq!(
+            follower
+                .cursor()
+                .recent
+                .iter()
+                .map(|b| b.hash.as_str())
+                .collect::<Vec<_>>(),
+            vec!["0xb1", "0xb2", "0xb3"]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::csv_scan::{self, Checkpoint, Entry};
+use super::jsonrpc::{self, ErrorKind};
+use super::pool::Pool;
+use super::settings;
+use super::tokens;
+use super::units;
+
+use std::collections::HashMap;
+
+use primitive_types::U256;
+
+/// Transfer(address,address,uint256)
+pub const TRANSFER_TOPIC: &str =
+    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// Blocks asked for in the first eth_getLogs request. The range doubles while the node
+/// answers with less than FEW_LOGS logs, up to MAX_RANGE, and halves when the node refuses it.
+const INITIAL_RANGE: u64 = 2_000;
+const MAX_RANGE: u64 = 1_000_000;
+const FEW_LOGS: usize = 1_000;
+
+/// Builds the holder list of a token from its Transfer logs and scans it like a csv file.
+/// The progress is saved per token and from_block, so a scan can be resumed up to a later
+/// block.
+pub async fn run_holders(
+    chain: &Chain,
+    token: &Address,
+    from_block: u64,
+    to_block: Option<u64>,
+    min_balance: U256,
+    checkpoint: Checkpoint,
+) {
+    //Load settings
+    let setting = settings::Settings::new(chain).unwrap_or_else(|err| {
+        println!(
+            "Couldn't load settings file.
+            \nTry running merter config --chain {} \n{}",
+            chain, err
+        );
+        std::process::exit(1);
+    });
+
+    let pool = Pool::for_chain(&setting.jsonrpc, chain)
+        .await
+        .unwrap_or_else(|err| {
+            println!(
+                "Error: {} \nTry running merter config --chain {}",
+                err, chain
+            );
+            std::process::exit(1);
+        });
+
+    let to_block = match to_block {
+        Some(n) => n,
+        None => pool
+            .request(|url| async move { jsonrpc::get_block_number(&url).await })
+            .await
+            .unwrap_or_else(|err| {
+                println!("Error while grabbing the latest block number: \n{}", err);
+                std::process::exit(1);
+            }),
+    };
+
+    let decimals = token_decimals(&pool, token).await.unwrap_or_else(|err| {
+        println!(
+            "Couldn't grab decimals of {}, is it an ERC-20 token? \nError: {}",
+            token.checksum(),
+            err
+        );
+        std::process::exit(1);
+    });
+
+    println!(
+        "Grabbing Transfer logs of {} from block {} to {}",
+        token.checksum(),
+        from_block,
+        to_block
+    );
+    let balances = fetch_balances(&pool, token, from_block, to_block)
+        .await
+        .unwrap_or_else(|err| {
+            println!("Error while grabbing Transfer logs: \n{}", err);
+            std::process::exit(1);
+        });
+
+    let holders = to_entries(balances, decimals, min_balance);
+    println!("{} holders above the minimum balance", holders.len());
+
+    let path = format!("token:{}", token);
+    let hash = format!("from block {}", from_block);
+    csv_scan::scan_holders(chain, &setting, &pool, holders, &path, &hash, checkpoint).await;
+}
+
+async fn token_decimals(
+    pool: &Pool,
+    token: &Address,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let decimals = pool
+        .request(|url| async move { jsonrpc::eth_call(token, tokens::DECIMALS, &url).await })
+        .await?;
+    let decimals = units::parse_hex_u256(&decimals)?;
+    if decimals > U256::from(77) {
+        return Err(format!("invalid decimals {}", decimals).into());
+    }
+    Ok(decimals.as_usize())
+}
+
+/// Returns the balance of every address from the Transfer logs of a token between from_block
+/// and to_block. A balance is what the address received minus what it sent, when from_block
+/// is after the token was deployed that's only the change in the range and balances that went
+/// down count as 0.
+pub async fn fetch_balances(
+    pool: &Pool,
+    token: &Address,
+    from_block: u64,
+    to_block: u64,
+) -> Result<HashMap<Address, U256>, jsonrpc::Error> {
+    let mut received: HashMap<Address, U256> = HashMap::new();
+    let mut sent: HashMap<Address, U256> = HashMap::new();
+
+    let mut start = from_block;
+    let mut range = INITIAL_RANGE;
+    while start <= to_block {
+        let end = start.saturating_add(range - 1).min(to_block);
+        let logs = pool
+            .request(|url| async move {
+                jsonrpc::get_logs(&url, token, TRANSFER_TOPIC, start, end).await
+            })
+            .await;
+
+        match logs {
+            Ok(logs) => {
+                for (from, to, value) in logs.iter().filter_map(transfer) {
+                    let sent = sent.entry(from).or_default();
+                    *sent = sent.saturating_add(value);
+                    let received = received.entry(to).or_default();
+                    *received = received.saturating_add(value);
+                }
+                if logs.len() < FEW_LOGS {
+                    range = (range * 2).min(MAX_RANGE);
+                }
+                if end == u64::MAX {
+                    break;
+                }
+                start = end + 1;
+            }
+            Err(e) if e.kind() == ErrorKind::TooLarge && end > start => {
+                range = (end - start).div_ceil(2);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(received
+        .into_iter()
+        .filter(|(address, _)| *address != Address::zero())
+        .map(|(address, received)| {
+            let sent = sent.get(&address).copied().unwrap_or_default();
+            (address, received.saturating_sub(sent))
+        })
+        .filter(|(_, balance)| !balance.is_zero())
+        .collect())
+}
+
+/// Returns the sender, receiver and amount of an ERC-20 Transfer log. ERC-721 Transfers have
+/// the token id as third topic and are skipped.
+fn transfer(log: &jsonrpc::Log) -> Option<(Address, Address, U256)> {
+    if log.topics.len() != 3 || log.topics[0] != TRANSFER_TOPIC {
+        return None;
+    }
+    let value = units::parse_hex_u256(&log.data).ok()?;
+    Some((
+        Address::from_word(&log.topics[1])?,
+        Address::from_word(&log.topics[2])?,
+        value,
+    ))
+}
+
+/// Converts balances in the token's smallest unit to holder list entries above min_balance
+fn to_entries(balances: HashMap<Address, U256>, decimals: usize, min_balance: U256) -> Vec<Entry> {
+    balances
+        .into_iter()
+        .map(|(address, balance)| {
+            let balance = if decimals <= csv_scan::BALANCE_DECIMALS {
+                balance.saturating_mul(U256::exp10(csv_scan::BALANCE_DECIMALS - decimals))
+            } else {
+                balance / U256::exp10(decimals - csv_scan::BALANCE_DECIMALS)
+            };
+            Entry { address, balance }
+        })
+        .filter(|entry| entry.balance > min_balance)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    const TOKEN: &str = "0x7777777777777777777777777777777777777777";
+    const ALICE: &str = "0x1111111111111111111111111111111111111111";
+    const BOB: &str = "0x2222222222222222222222222222222222222222";
+    const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+    fn address(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
+    fn topic(address: &str) -> String {
+        format!("0x{:0>64}", address.trim_start_matches("0x"))
+    }
+
+    fn transfer_log(block: u64, from: &str, to: &str, value: u64) -> Value {
+        json!({
+            "address": TOKEN,
+            "topics": [TRANSFER_TOPIC, topic(from), topic(to)],
+            "data": format!("{:#066x}", value),
+            "blockNumber": format!("{:#x}", block),
+        })
+    }
+
+    /// Serves canned Transfer logs and refuses ranges over max_range blocks
+    async fn mock_node(logs: Vec<Value>, max_range: u64) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(move |request: &Request| {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                let filter = &body["params"][0];
+                let block = |key: &str| {
+                    jsonrpc::parse_hex_u64(filter[key].as_str().unwrap()).unwrap()
+                };
+                let (from, to) = (block("fromBlock"), block("toBlock"));
+                if to - from + 1 > max_range {
+                    return ResponseTemplate::new(200).set_body_json(json!({
+                        "jsonrpc": "2.0",
+                        "id": 1,
+                        "error": { "code": -32005, "message": "query returned more than 10000 results" },
+                    }));
+                }
+
+                let result: Vec<&Value> = logs
+                    .iter()
+                    .filter(|log| {
+                        let n = jsonrpc::parse_hex_u64(log["blockNumber"].as_str().unwrap())
+                            .unwrap();
+                        from <= n && n <= to
+                    })
+                    .collect();
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "result": result,
+                }))
+            })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_fetch_balances() {
+        let logs = vec![
+            transfer_log(10, ZERO_ADDRESS, ALICE, 100),
+            transfer_log(5_000, ALICE, BOB, 30),
+            transfer_log(9_000, BOB, ALICE, 5),
+            transfer_log(9_500, BOB, ZERO_ADDRESS, 25),
+            // ERC-721 Transfer, the token id is the third topic
+            json!({
+                "address": TOKEN,
+                "topics": [TRANSFER_TOPIC, topic(ALICE), topic(BOB), topic("0x1")],
+                "data": "0x",
+                "blockNumber": "0x2710",
+            }),
+        ];
+        let server = mock_node(logs, 3_000).await;
+        let pool = Pool::single(&server.uri());
+
+        let balances = fetch_balances(&pool, &address(TOKEN), 0, 10_000)
+            .await
+            .unwrap();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[&address(ALICE)], U256::from(75));
+
+        // Bob's balance is 0 after burning, before that it's 30
+        let balances = fetch_balances(&pool, &address(TOKEN), 0, 8_999)
+            .await
+            .unwrap();
+        assert_eq!(balances[&address(BOB)], U256::from(30));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_balances_splits_refused_ranges() {
+        let logs: Vec<Value> = (0..20)
+            .map(|i| transfer_log(i * 100, ZERO_ADDRESS, ALICE, 1))
+            .collect();
+        let server = mock_node(logs, 150).await;
+        let pool = Pool::single(&server.uri());
+
+        let balances = fetch_balances(&pool, &address(TOKEN), 0, 1_999)
+            .await
+            .unwrap();
+        assert_eq!(balances[&address(ALICE)], U256::from(20));
+        // A refused range doesn't count against the node
+        assert!(pool.status()[0].available);
+
+        let server = mock_node(vec![], 0).await;
+        let error = fetch_balances(&Pool::single(&server.uri()), &address(TOKEN), 0, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TooLarge);
+    }
+
+    #[test]
+    fn test_to_entries() {
+        let mut balances = HashMap::new();
+        balances.insert(address(ALICE), U256::from(1_500_000));
+        balances.insert(address(BOB), U256::from(500_000));
+
+        let min_balance = units::parse_units("1", csv_scan::BALANCE_DECIMALS).unwrap();
+        let entries = to_entries(balances, 6, min_balance);
+        assert_eq!(
+            entries,
+            vec![Entry {
+                address: address(ALICE),
+                balance: units::parse_units("1.5", csv_scan::BALANCE_DECIMALS).unwrap(),
+            }]
+        );
+    }
+}
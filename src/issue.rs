@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Represents the severity of an issue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    /// Parses a severity, anything that isn't low, medium or high is unknown
+    pub fn parse(severity: &str) -> Self {
+        match severity.to_ascii_lowercase().as_str() {
+            "low" => Severity::Low,
+            "medium" => Severity::Medium,
+            "high" => Severity::High,
+            _ => Severity::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Severity::Unknown => "Unknown",
+            Severity::Low => "Low",
+            Severity::Medium => "Medium",
+            Severity::High => "High",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Represents the place in the source code where an issue was found
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SourceLocation {
+    pub file: String,
+    pub offset: usize,
+    pub length: usize,
+    pub line: Option<usize>,
+}
+
+/// Represents a vulnerability found in a contract
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Issue {
+    pub swc_id: String,
+    pub title: String,
+    pub severity: Severity,
+    pub description: String,
+    pub locations: Vec<SourceLocation>,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {} {}", self.severity, self.swc_id, self.title)?;
+        for location in &self.locations {
+            match location.line {
+                Some(line) => write!(f, "\n    {}:{}", location.file, line)?,
+                None => write!(f, "\n    {}@{}", location.file, location.offset)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,118 +1,540 @@
+use super::address::Address;
+use super::units;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use primitive_types::U256;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Returns the client every request goes through, so connections to the nodes are reused
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 enum Params {
     String(String),
     Boolean(bool),
+    Call(EthCallObj),
+    Filter(LogFilter),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EthCallObj {
+    to: Address,
+    data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogFilter {
+    address: Address,
+    topics: Vec<String>,
+    from_block: String,
+    to_block: String,
+}
+
+/// Represents an event log as returned by eth_getLogs
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<String>,
+    pub data: String,
+    #[serde(default)]
+    pub block_number: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct EthRequest {
     jsonrpc: String,
     method: String,
-    params: [Params; 2],
+    params: Vec<Params>,
     id: i32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct EthTransactionObj {
-    from: String,
-    to: String,
+    #[serde(default)]
+    pub hash: Option<String>,
+    pub from: Address,
+    /// None for contract creations
+    #[serde(default)]
+    pub to: Option<Address>,
 }
 
+/// Represents the receipt of a mined transaction, contract_address is set when the
+/// transaction created a contract
 #[derive(Debug, Deserialize)]
-pub struct EthTransactions {
-    transactions: Vec<EthTransactionObj>,
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    #[serde(default)]
+    pub contract_address: Option<Address>,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct EthBlockTxResponse {
-    pub result: EthTransactions,
+pub struct EthTransactions {
+    #[serde(default)]
+    pub number: Option<String>,
+    #[serde(default)]
+    pub hash: Option<String>,
+    #[serde(default, rename = "parentHash")]
+    pub parent_hash: Option<String>,
+    pub transactions: Vec<EthTransactionObj>,
 }
 
+/// Represents a json-rpc response, a node sets either result or error
 #[derive(Debug, Deserialize)]
-pub struct EthBlockCtResponse {
-    result: String,
+struct Response<T> {
+    #[serde(default)]
+    id: Option<i64>,
+    result: Option<T>,
+    error: Option<RpcError>,
 }
 
-///Represents a response from is_contract() contains is_contract true if it is a contract
-pub struct IsContractResponse {
-    pub address: String,
-    pub is_contract: bool,
-    pub count: u8,
+impl<T> Response<T> {
+    fn into_result(self) -> Result<T, Error> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(Error::Rpc(error)),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(Error::Decode("response without result".to_string())),
+        }
+    }
 }
 
-pub struct IsContractErr {
-    pub container: IsContractResponse,
-    pub error: reqwest::Error,
+/// Represents the error object of a json-rpc response
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
 }
 
-pub async fn is_contract(
-    container: IsContractResponse,
-    json_rpc_api: String,
-) -> Result<IsContractResponse, IsContractErr> {
-    let mut container = container;
-    container.count += 1;
+/// Messages nodes use when a query covers too many blocks or returns too many logs, some
+/// use -32005 for it, the code for a rate limit
+const TOO_LARGE_MESSAGES: [&str; 5] = [
+    "query returned more than",
+    "block range",
+    "range too large",
+    "too many results",
+    "response size exceeded",
+];
+
+/// Messages nodes use for state they don't keep anymore, there's no dedicated error code
+const PRUNED_MESSAGES: [&str; 5] = [
+    "missing trie node",
+    "header not found",
+    "pruned",
+    "state is not available",
+    "historical state",
+];
 
-    let res = match is_contract_reqwest(&container.address, json_rpc_api).await {
-        Ok(res) => res,
-        Err(err) => {
-            return Err(IsContractErr {
-                container: container,
-                error: err,
-            })
+impl RpcError {
+    pub fn kind(&self) -> ErrorKind {
+        let message = self.message.to_lowercase();
+        let mentions = |words: &[&str]| words.iter().any(|w| message.contains(w));
+        match self.code {
+            _ if mentions(&TOO_LARGE_MESSAGES) => ErrorKind::TooLarge,
+            -32005 | 429 => ErrorKind::RateLimited,
+            _ if mentions(&["rate limit", "too many requests", "request limit"]) => {
+                ErrorKind::RateLimited
+            }
+            _ if mentions(&PRUNED_MESSAGES) => ErrorKind::PrunedState,
+            3 => ErrorKind::Reverted,
+            _ if mentions(&["revert"]) => ErrorKind::Reverted,
+            -32600 | -32602 => ErrorKind::InvalidRequest,
+            _ => ErrorKind::Unavailable,
         }
-    };
+    }
+}
+
+/// Represents the ways a json-rpc request can fail
+#[derive(Debug)]
+pub enum Error {
+    /// The node couldn't be reached
+    Http(reqwest::Error),
+    /// The node answered with a http error status and no json-rpc error
+    Status(u16),
+    /// The node answered with a json-rpc error
+    Rpc(RpcError),
+    /// The node's answer doesn't make sense
+    Decode(String),
+}
 
-    if res.result.chars().count() > 2 {
-        container.is_contract = true;
+/// Represents what a failed request says about the node and the request, it decides whether
+/// the request is retried and where
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The node is rate limiting us, retry elsewhere and give it a break
+    RateLimited,
+    /// The request is wrong, every node will refuse it, skip it
+    InvalidRequest,
+    /// The call reverted, every node will answer the same, skip it
+    Reverted,
+    /// The query asks for too much at once, split it up
+    TooLarge,
+    /// The node doesn't keep the state asked for, a node that keeps more may have it
+    PrunedState,
+    /// The node is down or misbehaving, retry elsewhere
+    Unavailable,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Status(429) => ErrorKind::RateLimited,
+            Error::Rpc(error) => error.kind(),
+            Error::Http(_) | Error::Status(_) | Error::Decode(_) => ErrorKind::Unavailable,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{}", e),
+            Error::Status(status) => write!(f, "http status {}", status),
+            Error::Rpc(e) => write!(f, "json-rpc error {}: {}", e.code, e.message),
+            Error::Decode(e) => write!(f, "invalid response: {}", e),
+        }
     }
+}
+
+impl std::error::Error for Error {}
 
-    Ok(container)
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
 }
 
-pub async fn is_contract_reqwest(
-    address: &str,
-    json_rpc_api: String,
-) -> Result<EthBlockCtResponse, reqwest::Error> {
-    let new_eth_request = EthRequest {
+/// Posts a request and decodes the answer. A 429 is always a rate limit, other http errors
+/// only count when the body isn't a json-rpc response.
+async fn post<B: Serialize, R: DeserializeOwned>(json_rpc_api: &str, body: &B) -> Result<R, Error> {
+    let response = client().post(json_rpc_api).json(body).send().await?;
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(Error::Status(status.as_u16()));
+    }
+
+    let bytes = response.bytes().await?;
+    serde_json::from_slice(&bytes).map_err(|e| {
+        if status.is_success() {
+            Error::Decode(e.to_string())
+        } else {
+            Error::Status(status.as_u16())
+        }
+    })
+}
+
+fn request(method: &str, params: Vec<Params>, id: i32) -> EthRequest {
+    EthRequest {
         jsonrpc: "2.0".to_string(),
-        method: "eth_getCode".to_string(),
-        params: [
-            Params::String(address.to_string()),
-            Params::String("latest".to_string()),
-        ],
-        id: 1,
-    };
+        method: method.to_string(),
+        params,
+        id,
+    }
+}
 
-    let new_eth_response: EthBlockCtResponse = reqwest::Client::new()
-        .post(&json_rpc_api)
-        .json(&new_eth_request)
-        .send()
+async fn call<T: DeserializeOwned>(
+    json_rpc_api: &str,
+    method: &str,
+    params: Vec<Params>,
+) -> Result<T, Error> {
+    post::<_, Response<T>>(json_rpc_api, &request(method, params, 1))
         .await?
-        .json()
-        .await?;
+        .into_result()
+}
 
-    Ok(new_eth_response)
+fn latest(address: &Address) -> Vec<Params> {
+    vec![
+        Params::String(address.to_string()),
+        Params::String("latest".to_string()),
+    ]
 }
 
-async fn get_latest_block(json_rpc_api: &str) -> Result<EthBlockTxResponse, reqwest::Error> {
-    let new_eth_request = EthRequest {
-        jsonrpc: "2.0".to_string(),
-        method: "eth_getBlockByNumber".to_string(),
-        params: [Params::String("latest".to_string()), Params::Boolean(true)],
-        id: 1,
+pub async fn get_latest_block(json_rpc_api: &str) -> Result<EthTransactions, Error> {
+    get_block(json_rpc_api, "latest".to_string()).await
+}
+
+pub async fn get_block_by_number(
+    json_rpc_api: &str,
+    number: u64,
+) -> Result<EthTransactions, Error> {
+    get_block(json_rpc_api, format!("{:#x}", number)).await
+}
+
+async fn get_block(json_rpc_api: &str, tag: String) -> Result<EthTransactions, Error> {
+    call(
+        json_rpc_api,
+        "eth_getBlockByNumber",
+        vec![Params::String(tag), Params::Boolean(true)],
+    )
+    .await
+}
+
+/// Returns the receipt of a mined transaction.
+pub async fn get_transaction_receipt(hash: &str, json_rpc_api: &str) -> Result<Receipt, Error> {
+    call(
+        json_rpc_api,
+        "eth_getTransactionReceipt",
+        vec![Params::String(hash.to_string())],
+    )
+    .await
+}
+
+/// Returns the number of the most recent block.
+pub async fn get_block_number(json_rpc_api: &str) -> Result<u64, Error> {
+    let number: String = call(json_rpc_api, "eth_blockNumber", vec![]).await?;
+    parse_hex_u64(&number).map_err(|e| Error::Decode(e.to_string()))
+}
+
+/// Returns the id of the chain an endpoint serves. Nodes without eth_chainId are asked for
+/// net_version, which is the chain id on every chain merter supports.
+pub async fn get_chain_id(json_rpc_api: &str) -> Result<u64, Error> {
+    match call::<String>(json_rpc_api, "eth_chainId", vec![]).await {
+        Ok(id) => parse_hex_u64(&id).map_err(|e| Error::Decode(e.to_string())),
+        Err(e @ Error::Http(_)) => Err(e),
+        Err(e) => {
+            let version: String = call(json_rpc_api, "net_version", vec![])
+                .await
+                .map_err(|_| e)?;
+            version
+                .parse()
+                .map_err(|_| Error::Decode(format!("invalid net_version {:?}", version)))
+        }
+    }
+}
+
+/// Parses a 0x prefixed hex quantity as returned by the json-rpc api.
+pub fn parse_hex_u64(quantity: &str) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(u64::from_str_radix(quantity.trim_start_matches("0x"), 16)?)
+}
+
+/// Returns the balance of an address in wei.
+pub async fn get_balance(address: &Address, json_rpc_api: &str) -> Result<U256, Error> {
+    let balance: String = call(json_rpc_api, "eth_getBalance", latest(address)).await?;
+    units::parse_hex_u256(&balance).map_err(|e| Error::Decode(e.to_string()))
+}
+
+/// Executes a read-only call against a contract and returns the hex encoded return data.
+pub async fn eth_call(to: &Address, data: &str, json_rpc_api: &str) -> Result<String, Error> {
+    let params = vec![
+        Params::Call(EthCallObj {
+            to: *to,
+            data: data.to_string(),
+        }),
+        Params::String("latest".to_string()),
+    ];
+    call(json_rpc_api, "eth_call", params).await
+}
+
+/// Returns the deployed bytecode of an address, "0x" if it isn't a contract.
+pub async fn get_code(address: &Address, json_rpc_api: &str) -> Result<String, Error> {
+    call(json_rpc_api, "eth_getCode", latest(address)).await
+}
+
+/// Returns the 32 byte word stored in a storage slot of a contract.
+pub async fn get_storage_at(
+    address: &Address,
+    slot: &str,
+    json_rpc_api: &str,
+) -> Result<String, Error> {
+    let params = vec![
+        Params::String(address.to_string()),
+        Params::String(slot.to_string()),
+        Params::String("latest".to_string()),
+    ];
+    call(json_rpc_api, "eth_getStorageAt", params).await
+}
+
+/// Returns the logs of a contract with the given first topic between two blocks, inclusive.
+pub async fn get_logs(
+    json_rpc_api: &str,
+    address: &Address,
+    topic: &str,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Log>, Error> {
+    let filter = LogFilter {
+        address: *address,
+        topics: vec![topic.to_string()],
+        from_block: format!("{:#x}", from_block),
+        to_block: format!("{:#x}", to_block),
     };
+    call(json_rpc_api, "eth_getLogs", vec![Params::Filter(filter)]).await
+}
 
-    let new_eth_response: EthBlockTxResponse = reqwest::Client::new()
-        .post(json_rpc_api)
-        .json(&new_eth_request)
-        .send()
-        .await?
-        .json()
-        .await?;
+/// Returns the deployed bytecode of every address, sent as one batch request. The results are
+/// in the same order as the addresses, an address the node didn't answer for is an error.
+/// A node that refuses the whole batch answers with a single error, that's returned as is.
+pub async fn get_code_batch(
+    addresses: &[Address],
+    json_rpc_api: &str,
+) -> Result<Vec<Result<String, Error>>, Error> {
+    let requests: Vec<EthRequest> = addresses
+        .iter()
+        .enumerate()
+        .map(|(id, address)| request("eth_getCode", latest(address), id as i32))
+        .collect();
 
-    println!("{:#?}", new_eth_response);
-    //println!("{}", new_eth_response.result.transactions[0].from);
+    let responses: serde_json::Value = post(json_rpc_api, &requests).await?;
+    let responses: Vec<Response<String>> = match responses {
+        serde_json::Value::Array(_) => {
+            serde_json::from_value(responses).map_err(|e| Error::Decode(e.to_string()))?
+        }
+        _ => {
+            let response: Response<serde_json::Value> =
+                serde_json::from_value(responses).map_err(|e| Error::Decode(e.to_string()))?;
+            return Err(response
+                .into_result()
+                .err()
+                .unwrap_or_else(|| Error::Decode("batch answered with one result".to_string())));
+        }
+    };
+
+    // Nodes may answer in any order
+    let mut by_id: HashMap<i64, Response<String>> = responses
+        .into_iter()
+        .filter_map(|r| r.id.map(|id| (id, r)))
+        .collect();
+    Ok((0..addresses.len())
+        .map(|id| match by_id.remove(&(id as i64)) {
+            Some(response) => response.into_result(),
+            None => Err(Error::Decode("no response in batch".to_string())),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const ADDRESS: &str = "0x1111111111111111111111111111111111111111";
+
+    async fn mock_response(response: ResponseTemplate) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(response)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn rpc_error(code: i64, message: &str) -> ErrorKind {
+        RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+        .kind()
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(rpc_error(-32005, "limit exceeded"), ErrorKind::RateLimited);
+        assert_eq!(
+            rpc_error(-32000, "Too Many Requests"),
+            ErrorKind::RateLimited
+        );
+        assert_eq!(
+            rpc_error(-32000, "missing trie node abc (path )"),
+            ErrorKind::PrunedState
+        );
+        assert_eq!(
+            rpc_error(-32000, "header not found"),
+            ErrorKind::PrunedState
+        );
+        assert_eq!(rpc_error(3, "execution reverted"), ErrorKind::Reverted);
+        assert_eq!(
+            rpc_error(-32005, "query returned more than 10000 results"),
+            ErrorKind::TooLarge
+        );
+        assert_eq!(
+            rpc_error(-32602, "invalid argument 0"),
+            ErrorKind::InvalidRequest
+        );
+        assert_eq!(rpc_error(-32603, "internal error"), ErrorKind::Unavailable);
+        assert_eq!(Error::Status(429).kind(), ErrorKind::RateLimited);
+        assert_eq!(Error::Status(502).kind(), ErrorKind::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn test_error_envelope() {
+        let server = mock_response(ResponseTemplate::new(200).set_body_json(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32602, "message": "invalid address" },
+        })))
+        .await;
+        match get_code(&ADDRESS.parse().unwrap(), &server.uri()).await {
+            Err(Error::Rpc(e)) => assert_eq!(e.message, "invalid address"),
+            other => panic!("expected an rpc error, got {:?}", other),
+        }
+
+        // A json-rpc error behind a http error status is still read
+        let server = mock_response(ResponseTemplate::new(503).set_body_json(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32005, "message": "daily limit reached" },
+        })))
+        .await;
+        let error = get_code(&ADDRESS.parse().unwrap(), &server.uri())
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
 
-    Ok(new_eth_response)
+        let server = mock_response(ResponseTemplate::new(429)).await;
+        let error = get_code(&ADDRESS.parse().unwrap(), &server.uri())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Status(429)));
+
+        let server = mock_response(ResponseTemplate::new(502).set_body_string("Bad Gateway")).await;
+        let error = get_balance(&ADDRESS.parse().unwrap(), &server.uri())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Status(502)));
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_falls_back_to_net_version() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "eth_chainId" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "error": { "code": -32601, "message": "the method eth_chainId does not exist" },
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "net_version" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": "56",
+            })))
+            .mount(&server)
+            .await;
+        assert_eq!(get_chain_id(&server.uri()).await.unwrap(), 56);
+
+        let server = mock_response(ResponseTemplate::new(200).set_body_json(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": "0x38",
+        })))
+        .await;
+        assert_eq!(get_chain_id(&server.uri()).await.unwrap(), 56);
+    }
 }
@@ -4,22 +4,39 @@
 extern crate config;
 extern crate dirs;
 extern crate reqwest;
-extern crate text_io;
 extern crate toml;
 
 #[macro_use]
 extern crate serde;
 
+mod address;
+mod chain;
+mod code;
 mod csv_scan;
+mod db;
+mod explorer;
+mod find;
+mod follow;
+mod holders;
+mod issue;
 mod jsonrpc;
+mod mythx;
+mod pipeline;
+mod pool;
+mod proxy;
+mod ratelimit;
+mod schema;
+mod search;
 mod settings;
-mod timers;
+mod show;
+mod tokens;
+mod units;
 
-use clap::{App, AppSettings, Arg, ArgGroup};
-use serde::{Deserialize, Serialize};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use primitive_types::U256;
 
-/// Grabs the arguments from terminal and execute the correct branch. Currently there exist
-/// three branches (run_config(), run_csv() and run_find().
+/// Grabs the arguments from terminal and execute the correct subcommand. Currently there
+/// exist five subcommands (config, csv, find, search and show), each with its own arguments.
 ///
 /// More might be implemented in the future.
 
@@ -47,178 +64,441 @@ through. Together with a schema of the entry-points and flow of the contract.
 
 -----------------------------------------------------------------------------",
         )
-        .setting(AppSettings::ArgRequiredElseHelp)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
         .arg(
-            Arg::with_name("csv")
-                .short("c")
-                .long("csv")
+            Arg::with_name("chain")
+                .long("chain")
                 .takes_value(true)
-                .value_name("CSV")
+                .value_name("NAME")
+                .global(true)
                 .help(
-                    "Csv mode, the csv files need to be manually down-
-loaded from etherscan||bscscan
-/exportData?type=tokenholders&contract=[addr]
-or use the same formatting",
+                    "Selects the chain and its config file: eth, bsc, polygon,
+arbitrum, optimism, avalanche, fantom or a chain from
+chains.toml in the config dir",
                 ),
         )
         .arg(
-            Arg::with_name("find")
-                .short("f")
-                .long("find")
-                .takes_value(false)
-                .help(
-                    "Find mode, loops over the latest block, grabs all trans-
-actions, filters out contract addresses, checks value,
-downloads and scans them",
-                ),
+            Arg::with_name("ethereum")
+                .long("eth")
+                .global(true)
+                .conflicts_with_all(&["binance", "chain"])
+                .help("Same as --chain eth"),
         )
-        .arg(Arg::with_name("config").long("config").help(
-            "Set API keys, database, working directory and Json-RPC
+        .arg(
+            Arg::with_name("binance")
+                .long("bsc")
+                .global(true)
+                .conflicts_with("chain")
+                .help("Same as --chain bsc"),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about(
+                    "Set API keys, database, working directory and Json-RPC
 node. If you use your own node make shure it's behind a
 ngingx proxy that keeps the connections alive. The requests
 to the endpoint are done concurrently so it overloads the 
-node. If you use an API make shure to calculate latency
-according to rate limit.",
-        ))
-        .group(
-            ArgGroup::with_name("req_flags")
-                .args(&["csv", "find", "config"])
-                .required(true),
-        )
-        .arg(
-            Arg::with_name("balance")
-                .short("b")
-                .long("balance")
-                .takes_value(true)
-                .help(
-                    "Specify the minimum balance of the contract.
-In --csv mode the balance is denominated in the 
-current token.
-In --find mode the balance is denominated in eth
-or bnb.",
+node. If you use an API make shure to set the rate limit
+(requests_per_second and burst) of every endpoint.
+
+The current settings, as merter runs with them, are
+offered as defaults, press enter to keep them. The
+answers are written to the config file in the config dir.
+
+Every setting can be overridden with an environment
+variable MERTER_<CHAIN>_<SETTING>, e.g.
+MERTER_ETH_JSONRPC_URL_1 for jsonrpc.url_1 on eth. They
+take precedence over the config file in the working
+directory, which takes precedence over the one in the
+config dir.",
+                )
+                .arg(
+                    Arg::with_name("from-env")
+                        .long("from-env")
+                        .help("Writes the settings from MERTER_<CHAIN>_* environment variables"),
+                )
+                .arg(
+                    Arg::with_name("field")
+                        .long("field")
+                        .takes_value(true)
+                        .value_name("KEY")
+                        .help("Only asks for a single setting, e.g. scan.key"),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Sets a single setting, e.g. merter config set scan.key ABC --eth")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .index(1)
+                                .help("Setting to change, e.g. jsonrpc.url_1"),
+                        )
+                        .arg(Arg::with_name("value").required(true).index(2)),
                 ),
         )
-        .arg(
-            Arg::with_name("limit")
-                .short("l")
-                .long("limit")
-                .takes_value(true)
-                .help("Sets maximum amount of contracts to scan [mythx]"),
+        .subcommand(
+            SubCommand::with_name("csv")
+                .about(
+                    "Csv mode, the csv files need to be manually down-
+loaded from etherscan||bscscan
+/exportData?type=tokenholders&contract=[addr]
+or use the same formatting",
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .value_name("CSV")
+                        .help("Token holder list to scan"),
+                )
+                .arg(token_balance_arg())
+                .arg(limit_arg().help("Only checks the LIMIT holders with the highest balance"))
+                .arg(resume_arg())
+                .arg(restart_arg()),
         )
-        .arg(
-            Arg::with_name("ethereum")
-                .long("eth")
-                .takes_value(false)
-                .help("Selects ethereum config file"),
+        .subcommand(
+            SubCommand::with_name("holders")
+                .about(
+                    "Holders mode, builds the holder list of a token from its
+Transfer logs and scans it like csv mode",
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .required(true)
+                        .value_name("TOKEN")
+                        .validator(address_validator)
+                        .help("Address of the token contract"),
+                )
+                .arg(token_balance_arg())
+                .arg(
+                    Arg::with_name("from-block")
+                        .long("from-block")
+                        .takes_value(true)
+                        .value_name("BLOCK")
+                        .validator(block_validator)
+                        .help("First block to grab Transfer logs from, defaults to 0"),
+                )
+                .arg(
+                    Arg::with_name("to-block")
+                        .long("to-block")
+                        .takes_value(true)
+                        .value_name("BLOCK")
+                        .validator(block_validator)
+                        .help("Last block to grab Transfer logs from, defaults to the latest"),
+                )
+                .arg(resume_arg())
+                .arg(restart_arg()),
         )
-        .arg(
-            Arg::with_name("binance")
-                .long("bsc")
-                .takes_value(false)
-                .help("Selects binance smart chain config file"),
+        .subcommand(
+            SubCommand::with_name("find")
+                .about(
+                    "Find mode, loops over the latest block, grabs all trans-
+actions, filters out contract addresses, checks value,
+downloads and scans them",
+                )
+                .arg(
+                    Arg::with_name("balance")
+                        .short("b")
+                        .long("balance")
+                        .takes_value(true)
+                        .value_name("ETHER")
+                        .help(
+                            "Minimum value of the contract in the native coin,
+held tokens are counted by their value in the native
+coin (see [tokens] in the config file)",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("wei")
+                        .long("wei")
+                        .requires("balance")
+                        .help("Reads --balance as wei instead of the native coin"),
+                )
+                .arg(limit_arg())
+                .arg(Arg::with_name("follow").long("follow").help(
+                    "Keeps running, follows the chain and scans every new
+block. Progress is saved in the storage folder so a
+restart picks up where it stopped",
+                )),
         )
-        .group(
-            ArgGroup::with_name("chain_flags")
-                .args(&["ethereum", "binance"])
-                .required(true),
+        .subcommand(
+            SubCommand::with_name("search")
+                .about(
+                    "Searches the findings stored in the database, --chain
+only shows findings on that chain (default all chains
+in the eth database)",
+                )
+                .arg(
+                    Arg::with_name("swc")
+                        .long("swc")
+                        .takes_value(true)
+                        .help("Only shows issues with this SWC ID, e.g. SWC-107"),
+                )
+                .arg(
+                    Arg::with_name("severity")
+                        .long("severity")
+                        .takes_value(true)
+                        .possible_values(&["low", "medium", "high"])
+                        .help("Only shows issues of this severity or higher"),
+                )
+                .arg(
+                    Arg::with_name("balance")
+                        .short("b")
+                        .long("balance")
+                        .takes_value(true)
+                        .value_name("AMOUNT")
+                        .help("Minimum value of the contract in the native coin"),
+                )
+                .arg(
+                    Arg::with_name("compiler")
+                        .long("compiler")
+                        .takes_value(true)
+                        .help(
+                            "Only shows contracts compiled with this solc version
+prefix, e.g. 0.6 or 0.8.4",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .validator(|v| search::parse_date(&v).map(|_| ()))
+                        .help("Only shows issues found on or after this date"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .validator(|v| search::parse_date(&v).map(|_| ()))
+                        .help("Only shows issues found before this date"),
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .long("address")
+                        .takes_value(true)
+                        .validator(|v| search::parse_address_prefix(&v).map(|_| ()))
+                        .help("Only shows contracts whose address starts with this prefix"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["table", "json", "csv"])
+                        .default_value("table")
+                        .help("Output format"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Shows everything stored about a contract")
+                .arg(
+                    Arg::with_name("address")
+                        .required(true)
+                        .validator(address_validator)
+                        .help("Address of the contract"),
+                ),
         )
         .get_matches();
 
-    let mut chain: String = String::new();
-    if res.is_present("ethereum") {
-        chain = "eth".to_string();
-    }
-    if res.is_present("binance") {
-        chain = "bsc".to_string();
+    match res.subcommand() {
+        ("config", Some(args)) => match args.subcommand() {
+            ("set", Some(set)) => settings::run_set(
+                &require_chain(set),
+                set.value_of("key").unwrap(),
+                set.value_of("value").unwrap(),
+            ),
+            _ if args.is_present("from-env") => settings::run_from_env(&require_chain(args)),
+            _ => settings::run_setup(&require_chain(args), args.value_of("field")),
+        },
+        ("csv", Some(args)) => {
+            let chain = require_chain(args);
+            let csv_file = args.value_of("file").unwrap();
+            println!("Running in csv mode");
+            csv_scan::run_csv(
+                &chain,
+                token_balance(args),
+                scan_limit(args),
+                csv_file,
+                checkpoint(args),
+            )
+            .await;
+        }
+        ("holders", Some(args)) => {
+            let chain = require_chain(args);
+            let token: address::Address = args.value_of("token").unwrap().parse().unwrap();
+            let from_block = args
+                .value_of("from-block")
+                .map_or(0, |b| b.parse::<u64>().unwrap());
+            let to_block = args.value_of("to-block").map(|b| b.parse::<u64>().unwrap());
+            println!("Running in holders mode");
+            holders::run_holders(
+                &chain,
+                &token,
+                from_block,
+                to_block,
+                token_balance(args),
+                checkpoint(args),
+            )
+            .await;
+        }
+        ("find", Some(args)) => {
+            let chain = require_chain(args);
+            let balance = args.value_of("balance").unwrap_or("0");
+            let decimals = if args.is_present("wei") {
+                0
+            } else {
+                chain.decimals
+            };
+            let min_balance = units::parse_units(balance, decimals).unwrap_or_else(|err| {
+                println!("Error: --balance option must be a number \n{}", err);
+                std::process::exit(1);
+            });
+            println!("Running in find mode");
+            if args.is_present("follow") {
+                follow::run_follow(&chain, min_balance, scan_limit(args)).await;
+            } else {
+                find::run_find(&chain, min_balance, scan_limit(args)).await;
+            }
+        }
+        ("search", Some(args)) => {
+            // Validated by clap
+            let selected = selected_chain(args);
+            let chain = selected
+                .clone()
+                .unwrap_or_else(|| exit_on_unknown(chain::Chain::by_name("eth")));
+            let min_balance = args.value_of("balance").map(|balance| {
+                units::parse_units(balance, chain.decimals).unwrap_or_else(|err| {
+                    println!("Error: --balance option must be a number \n{}", err);
+                    std::process::exit(1);
+                })
+            });
+            let query = search::Query {
+                chain: selected.map(|chain| chain.name),
+                swc_id: args.value_of("swc").map(str::to_string),
+                min_severity: args.value_of("severity").map(issue::Severity::parse),
+                min_balance,
+                compiler: args.value_of("compiler").map(str::to_string),
+                since: args
+                    .value_of("since")
+                    .map(|d| search::parse_date(d).unwrap()),
+                until: args
+                    .value_of("until")
+                    .map(|d| search::parse_date(d).unwrap()),
+                address_prefix: args
+                    .value_of("address")
+                    .map(|a| search::parse_address_prefix(a).unwrap()),
+            };
+            let format = search::Format::parse(args.value_of("format").unwrap()).unwrap();
+            search::run_search(&chain, &query, format);
+        }
+        ("show", Some(args)) => {
+            let chain = require_chain(args);
+            let address = args.value_of("address").unwrap().parse().unwrap();
+            show::run_show(&chain, &address);
+        }
+        _ => {}
     }
 
-    let min_balance = res
-        .value_of("balance")
-        .unwrap_or("0")
-        .parse::<f32>()
-        .unwrap_or_else(|_| {
-            println!("Error: --balance option must be a number");
-            std::process::exit(1);
-        });
-
-    let scan_limit = res
-        .value_of("limit")
-        .unwrap_or("0")
-        .parse::<usize>()
-        .unwrap_or_else(|_| {
-            println!("Error: --limit option must be a positive number");
-            std::process::exit(1);
-        });
-
-    //Choosing branch to execute.
-    if res.is_present("config") {
-        settings::run_setup(&chain);
-    }
+    Ok(())
+}
 
-    if res.is_present("csv") {
-        let csv_file = res.value_of("csv").unwrap();
-        println!("Running in csv mode");
-        csv_scan::run_csv(&chain, min_balance, scan_limit, &csv_file).await;
-    }
+fn limit_arg() -> Arg<'static, 'static> {
+    Arg::with_name("limit")
+        .short("l")
+        .long("limit")
+        .takes_value(true)
+        .value_name("LIMIT")
+        .validator(|v| {
+            v.parse::<usize>()
+                .map(|_| ())
+                .map_err(|_| "must be a positive number".to_string())
+        })
+        .help("Sets maximum amount of contracts to scan [mythx]")
+}
+
+fn token_balance_arg() -> Arg<'static, 'static> {
+    Arg::with_name("balance")
+        .short("b")
+        .long("balance")
+        .takes_value(true)
+        .value_name("TOKENS")
+        .validator(|v| {
+            units::parse_units(&v, csv_scan::BALANCE_DECIMALS)
+                .map(|_| ())
+                .map_err(|_| "must be a number".to_string())
+        })
+        .help("Minimum balance of the contract, denominated in the token")
+}
+
+fn token_balance(args: &ArgMatches) -> U256 {
+    args.value_of("balance").map_or(U256::zero(), |b| {
+        units::parse_units(b, csv_scan::BALANCE_DECIMALS).unwrap()
+    })
+}
+
+fn resume_arg() -> Arg<'static, 'static> {
+    Arg::with_name("resume")
+        .long("resume")
+        .conflicts_with("restart")
+        .help("Continue an earlier scan of the same holder list")
+}
+
+fn restart_arg() -> Arg<'static, 'static> {
+    Arg::with_name("restart")
+        .long("restart")
+        .help("Throw away the progress of an earlier scan of the same holder list")
+}
 
-    if res.is_present("find") {
-        println!("Running in find mode");
+fn checkpoint(args: &ArgMatches) -> csv_scan::Checkpoint {
+    if args.is_present("resume") {
+        csv_scan::Checkpoint::Resume
+    } else if args.is_present("restart") {
+        csv_scan::Checkpoint::Restart
+    } else {
+        csv_scan::Checkpoint::Ask
     }
+}
 
-    Ok(())
+fn address_validator(v: String) -> Result<(), String> {
+    v.parse::<address::Address>()
+        .map(|_| ())
+        .map_err(|e| format!("{} is not an address: {}", v, e))
 }
 
-async fn run_find(
-    chain: &str,
-    min_balance: &f32,
-    limit: &u32,
-) -> Result<(), Box<dyn std::error::Error>> {
-    /*
-    let db: Db = Arc::new(Mutex::new(HashMap::new()));
-    let mut wg = WaitGroup::new();
-
-    let block_res = get_block_addresses().await;
-
-    match block_res {
-        Ok(v) => {
-            println!("Block downloaded, grabbing contracts");
-            println!("Txs: {}", v.result.transactions.len());
-
-            for obj in v.result.transactions {
-                let db1 = db.clone();
-                let db2 = db.clone();
-
-                let to = obj.to.clone();
-                let from = obj.from.clone();
-
-                let worker = wg.worker();
-                let worker1 = wg.worker();
-
-                let to_handle = tokio::spawn(async move {
-                    let resp = check_if_contract(to, db1).await;
-                    println!("{:?}", resp);
-                    worker.done();
-                });
-
-                let from_handle = tokio::spawn(async move {
-                    let resp = check_if_contract(from, db2).await;
-                    println!("{:?}", resp);
-                    worker1.done();
-                });
-            }
+fn block_validator(v: String) -> Result<(), String> {
+    v.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| "must be a block number".to_string())
+}
 
-            wg.wait().await;
+fn scan_limit(args: &ArgMatches) -> usize {
+    args.value_of("limit")
+        .map_or(0, |l| l.parse::<usize>().unwrap())
+}
 
-            let db3 = db.clone();
-            for (key, value) in db3.lock().unwrap().iter() {
-                println!("{}: {}", key, value);
-            }
-        }
+fn selected_chain(args: &ArgMatches) -> Option<chain::Chain> {
+    let name = if args.is_present("ethereum") {
+        "eth"
+    } else if args.is_present("binance") {
+        "bsc"
+    } else {
+        args.value_of("chain")?
+    };
+    Some(exit_on_unknown(chain::Chain::by_name(name)))
+}
 
-        Err(e) => {
-            println!("error parsing header: {:?}", e);
-        }
-    }
-    */
-    Ok(())
+fn require_chain(args: &ArgMatches) -> chain::Chain {
+    selected_chain(args).unwrap_or_else(|| {
+        println!("Error: select a chain with --chain, e.g. --chain eth");
+        std::process::exit(1);
+    })
+}
+
+fn exit_on_unknown(chain: Result<chain::Chain, String>) -> chain::Chain {
+    chain.unwrap_or_else(|err| {
+        println!("Error: {}", err);
+        std::process::exit(1);
+    })
 }
@@ -0,0 +1,580 @@
+use super::explorer::Metadata;
+use super::issue::{Issue, Severity, SourceLocation};
+use super::ratelimit::RateLimiter;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub const API_URL: &str = "https://api.mythx.io";
+
+/// Represents the status of a submitted analysis
+#[derive(Debug, Deserialize)]
+struct AnalysisStatus {
+    uuid: String,
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    #[serde(rename = "jwtTokens")]
+    jwt_tokens: JwtTokens,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtTokens {
+    access: String,
+}
+
+/// Represents one report of the issues endpoint, there is one for every analyzed source type
+#[derive(Debug, Deserialize)]
+struct IssueReport {
+    issues: Vec<MythXIssue>,
+    #[serde(default, rename = "sourceList")]
+    source_list: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MythXIssue {
+    #[serde(rename = "swcID")]
+    swc_id: String,
+    #[serde(rename = "swcTitle")]
+    swc_title: String,
+    severity: String,
+    description: Description,
+    #[serde(default)]
+    locations: Vec<MythXLocation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Description {
+    head: String,
+    tail: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MythXLocation {
+    #[serde(rename = "sourceMap")]
+    source_map: String,
+    #[serde(default, rename = "sourceList")]
+    source_list: Vec<String>,
+}
+
+/// Represents everything MythX needs to analyze a contract
+#[derive(Debug, Serialize)]
+pub struct AnalysisInput {
+    pub contract_name: String,
+    pub solc_version: String,
+    pub main_source: String,
+    pub sources: BTreeMap<String, String>,
+    pub deployed_bytecode: String,
+}
+
+impl AnalysisInput {
+    /// Builds the input from a directory written by explorer::write_source and the deployed
+    /// bytecode of the contract
+    pub fn from_dir(dir: &Path, bytecode: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let metadata: Metadata =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("metadata.json"))?)?;
+
+        let mut sources = BTreeMap::new();
+        read_sources(dir, dir, &mut sources)?;
+        if sources.is_empty() {
+            return Err(format!("no source files in {}", dir.display()).into());
+        }
+
+        let main_source = sources
+            .iter()
+            .find(|(_, source)| declares(source, &metadata.name))
+            .or_else(|| sources.iter().next())
+            .map(|(name, _)| name.clone())
+            .unwrap_or_default();
+
+        Ok(AnalysisInput {
+            contract_name: metadata.name,
+            solc_version: solc_version(&metadata.compiler_version),
+            main_source,
+            sources,
+            deployed_bytecode: bytecode.to_string(),
+        })
+    }
+}
+
+/// Returns true when a source file declares the contract, a declaration of a contract of
+/// which the name only starts with the same name doesn't count
+fn declares(source: &str, name: &str) -> bool {
+    let declaration = format!("contract {}", name);
+    source.match_indices(&declaration).any(|(i, _)| {
+        source[i + declaration.len()..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_whitespace() || c == '{')
+    })
+}
+
+/// Client for the MythX api
+pub struct MythX {
+    client: reqwest::Client,
+    api_url: String,
+    key: String,
+    access_token: Option<String>,
+    poll_interval: Duration,
+    max_poll_interval: Duration,
+    timeout: Duration,
+    limiter: RateLimiter,
+}
+
+impl MythX {
+    pub fn new(api_url: &str, key: &str) -> Self {
+        MythX {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            key: key.to_string(),
+            access_token: None,
+            poll_interval: Duration::from_secs(5),
+            max_poll_interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(60 * 30),
+            limiter: RateLimiter::unlimited(),
+        }
+    }
+
+    /// Sends every request through limiter
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Sets the first poll interval, the interval doubles after every poll up to max. Gives up
+    /// on an analysis after timeout.
+    pub fn with_polling(mut self, interval: Duration, max: Duration, timeout: Duration) -> Self {
+        self.poll_interval = interval;
+        self.max_poll_interval = max;
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gets an access token. A key in the form of username:password is exchanged for a token,
+    /// any other key is an api key generated in the MythX dashboard and used as token.
+    pub async fn authenticate(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (username, password) = match self.key.split_once(':') {
+            Some(credentials) => credentials,
+            None => {
+                self.access_token = Some(self.key.clone());
+                return Ok(());
+            }
+        };
+
+        self.limiter.acquire().await;
+        let response = self
+            .client
+            .post(format!("{}/v1/auth/login", self.api_url))
+            .json(&json!({ "username": username, "password": password }))
+            .send()
+            .await?
+            .error_for_status()?;
+        let login: LoginResponse = response.json().await?;
+
+        self.access_token = Some(login.jwt_tokens.access);
+        Ok(())
+    }
+
+    fn token(&self) -> Result<&str, Box<dyn Error + Send + Sync>> {
+        match &self.access_token {
+            Some(token) => Ok(token),
+            None => Err("not authenticated with MythX".into()),
+        }
+    }
+
+    /// Submits an analysis and returns its uuid
+    pub async fn submit(
+        &self,
+        input: &AnalysisInput,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let sources: serde_json::Map<String, serde_json::Value> = input
+            .sources
+            .iter()
+            .map(|(name, source)| (name.clone(), json!({ "source": source })))
+            .collect();
+
+        let body = json!({
+            "clientToolName": "merter",
+            "data": {
+                "contractName": input.contract_name,
+                "deployedBytecode": input.deployed_bytecode,
+                "mainSource": input.main_source,
+                "sourceList": input.sources.keys().collect::<Vec<_>>(),
+                "sources": sources,
+                "solcVersion": input.solc_version,
+                "analysisMode": "quick",
+            }
+        });
+
+        self.limiter.acquire().await;
+        let status: AnalysisStatus = self
+            .client
+            .post(format!("{}/v1/analyses", self.api_url))
+            .bearer_auth(self.token()?)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(status.uuid)
+    }
+
+    /// Polls the status of an analysis until it's finished, backing off between polls
+    pub async fn wait(&self, uuid: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut interval = self.poll_interval;
+        let mut waited = Duration::from_secs(0);
+
+        loop {
+            self.limiter.acquire().await;
+            let status: AnalysisStatus = self
+                .client
+                .get(format!("{}/v1/analyses/{}", self.api_url, uuid))
+                .bearer_auth(self.token()?)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            match status.status.as_str() {
+                "Finished" => return Ok(()),
+                "Error" => {
+                    return Err(format!(
+                        "analysis {} failed: {}",
+                        uuid,
+                        status.error.unwrap_or_default()
+                    )
+                    .into())
+                }
+                _ => {}
+            }
+
+            if waited >= self.timeout {
+                return Err(format!("analysis {} timed out", uuid).into());
+            }
+            tokio::time::sleep(interval).await;
+            waited += interval;
+            interval = std::cmp::min(interval * 2, self.max_poll_interval);
+        }
+    }
+
+    /// Grabs the detected issues of a finished analysis
+    pub async fn issues(
+        &self,
+        uuid: &str,
+        input: &AnalysisInput,
+    ) -> Result<Vec<Issue>, Box<dyn Error + Send + Sync>> {
+        self.limiter.acquire().await;
+        let reports: Vec<IssueReport> = self
+            .client
+            .get(format!("{}/v1/analyses/{}/issues", self.api_url, uuid))
+            .bearer_auth(self.token()?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(reports
+            .into_iter()
+            .flat_map(|report| {
+                let source_list = report.source_list;
+                report
+                    .issues
+                    .into_iter()
+                    .map(move |issue| to_issue(issue, &source_list, &input.sources))
+            })
+            .collect())
+    }
+}
+
+/// Maps a MythX issue to a merter issue, resolving the source maps to files and lines
+fn to_issue(
+    issue: MythXIssue,
+    source_list: &[String],
+    sources: &BTreeMap<String, String>,
+) -> Issue {
+    let locations = issue
+        .locations
+        .iter()
+        .filter_map(|location| {
+            let list = if location.source_list.is_empty() {
+                source_list
+            } else {
+                &location.source_list
+            };
+            to_location(&location.source_map, list, sources)
+        })
+        .collect();
+
+    let description = if issue.description.tail.is_empty() {
+        issue.description.head
+    } else {
+        format!("{} {}", issue.description.head, issue.description.tail)
+    };
+
+    Issue {
+        swc_id: issue.swc_id,
+        title: issue.swc_title,
+        severity: Severity::parse(&issue.severity),
+        description,
+        locations,
+    }
+}
+
+/// Parses a source map entry in the form of offset:length:file_index
+fn to_location(
+    source_map: &str,
+    source_list: &[String],
+    sources: &BTreeMap<String, String>,
+) -> Option<SourceLocation> {
+    let mut parts = source_map.split(':');
+    let offset: usize = parts.next()?.parse().ok()?;
+    let length: usize = parts.next()?.parse().ok()?;
+    let index: usize = parts.next()?.parse().ok()?;
+    let file = source_list.get(index)?.clone();
+
+    let line = sources
+        .get(&file)
+        .filter(|source| offset <= source.len())
+        .map(|source| {
+            source.as_bytes()[..offset]
+                .iter()
+                .filter(|b| **b == b'\n')
+                .count()
+                + 1
+        });
+
+    Some(SourceLocation {
+        file,
+        offset,
+        length,
+        line,
+    })
+}
+
+/// Turns a compiler version like v0.8.4+commit.c7e474f2 into 0.8.4
+fn solc_version(compiler_version: &str) -> String {
+    compiler_version
+        .trim_start_matches('v')
+        .split('+')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Reads all solidity files below dir, keyed by their path relative to root
+pub fn read_sources(
+    root: &Path,
+    dir: &Path,
+    sources: &mut BTreeMap<String, String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_sources(root, &path, sources)?;
+        } else if path.extension().is_some_and(|ext| ext == "sol") {
+            let name = path
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            sources.insert(name, std::fs::read_to_string(&path)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    pub(crate) const UUID: &str = "ab9092f7-54d0-480f-9b63-1bb1508280e2";
+    pub(crate) const SOURCE: &str =
+        "pragma solidity 0.8.4;\ncontract Vault {\n  function withdraw() public {}\n}\n";
+
+    fn input() -> AnalysisInput {
+        let mut sources = BTreeMap::new();
+        sources.insert("Vault.sol".to_string(), SOURCE.to_string());
+        AnalysisInput {
+            contract_name: "Vault".to_string(),
+            solc_version: "0.8.4".to_string(),
+            main_source: "Vault.sol".to_string(),
+            sources,
+            deployed_bytecode: "0x6080".to_string(),
+        }
+    }
+
+    pub(crate) fn client(server: &MockServer, key: &str) -> MythX {
+        MythX::new(&server.uri(), key).with_polling(
+            Duration::from_millis(1),
+            Duration::from_millis(4),
+            Duration::from_secs(5),
+        )
+    }
+
+    /// Serves an analysis of Vault that finds one reentrancy issue
+    pub(crate) async fn mock_mythx(server: &MockServer, token: &str) {
+        Mock::given(method("POST"))
+            .and(path("/v1/analyses"))
+            .and(header("authorization", format!("Bearer {}", token).as_str()))
+            .and(body_partial_json(json!({
+                "data": { "contractName": "Vault", "mainSource": "Vault.sol", "solcVersion": "0.8.4" }
+            })))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "uuid": UUID, "status": "Queued" })),
+            )
+            .mount(server)
+            .await;
+
+        // Two polls in progress before it's finished
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/analyses/{}", UUID)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "uuid": UUID, "status": "In progress" })),
+            )
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/analyses/{}", UUID)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "uuid": UUID, "status": "Finished" })),
+            )
+            .mount(server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/analyses/{}/issues", UUID)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+                "issues": [{
+                    "swcID": "SWC-107",
+                    "swcTitle": "Reentrancy",
+                    "description": { "head": "Read of persistent state following external call.", "tail": "" },
+                    "severity": "High",
+                    "locations": [{ "sourceMap": "42:10:0" }],
+                    "extra": {}
+                }],
+                "sourceType": "solidity-file",
+                "sourceFormat": "text",
+                "sourceList": ["Vault.sol"],
+                "meta": {}
+            }])))
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn test_solc_version() {
+        assert_eq!(solc_version("v0.8.4+commit.c7e474f2"), "0.8.4");
+        assert_eq!(solc_version("0.5.17"), "0.5.17");
+    }
+
+    #[test]
+    fn test_to_location() {
+        let mut sources = BTreeMap::new();
+        sources.insert("Vault.sol".to_string(), SOURCE.to_string());
+        let list = vec!["Vault.sol".to_string()];
+
+        let location = to_location("42:10:0", &list, &sources).unwrap();
+        assert_eq!(location.file, "Vault.sol");
+        assert_eq!(location.line, Some(3));
+        assert!(to_location("42:10:1", &list, &sources).is_none());
+        assert!(to_location("garbage", &list, &sources).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_submit_with_login() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/auth/login"))
+            .and(body_partial_json(
+                json!({ "username": "user", "password": "secret" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jwtTokens": { "access": "ACCESS", "refresh": "REFRESH" }
+            })))
+            .mount(&server)
+            .await;
+        mock_mythx(&server, "ACCESS").await;
+
+        let mut mythx = client(&server, "user:secret");
+        mythx.authenticate().await.unwrap();
+        let uuid = mythx.submit(&input()).await.unwrap();
+        assert_eq!(uuid, UUID);
+        mythx.wait(&uuid).await.unwrap();
+        assert_eq!(mythx.issues(&uuid, &input()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_analysis() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/analyses/{}", UUID)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "uuid": UUID, "status": "Error", "error": "compilation failed"
+            })))
+            .mount(&server)
+            .await;
+
+        let mut mythx = client(&server, "API_KEY");
+        mythx.authenticate().await.unwrap();
+        let err = mythx.wait(UUID).await.unwrap_err();
+        assert!(err.to_string().contains("compilation failed"));
+    }
+
+    #[test]
+    fn test_input_from_dir() {
+        let dir = std::env::temp_dir().join(format!("merter_mythx_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("Vault.sol"), SOURCE).unwrap();
+        std::fs::write(dir.join("lib/Lib.sol"), "library Lib {}").unwrap();
+        std::fs::write(
+            dir.join("Base.sol"),
+            "abstract contract VaultBase {}\ncontract Vault2 {}",
+        )
+        .unwrap();
+        std::fs::write(dir.join("abi.json"), "[]").unwrap();
+        let metadata = Metadata {
+            address: "0x1111111111111111111111111111111111111111"
+                .parse()
+                .unwrap(),
+            name: "Vault".to_string(),
+            compiler_version: "v0.8.4+commit.c7e474f2".to_string(),
+            optimization_used: true,
+            runs: 200,
+            evm_version: "Default".to_string(),
+            implementation: None,
+        };
+        std::fs::write(
+            dir.join("metadata.json"),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .unwrap();
+
+        let input = AnalysisInput::from_dir(&dir, "0x6080").unwrap();
+        assert_eq!(input.main_source, "Vault.sol");
+        assert_eq!(input.solc_version, "0.8.4");
+        assert_eq!(
+            input.sources.keys().collect::<Vec<_>>(),
+            vec!["Base.sol", "Vault.sol", "lib/Lib.sol"]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::db;
+use super::explorer;
+use super::find::Contract;
+use super::jsonrpc;
+use super::mythx;
+use super::pool::Pool;
+use super::proxy;
+use super::ratelimit::{Limiters, RateLimiter};
+use super::schema;
+use super::settings;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Runs found contracts through the rest of merter: stores them in the database, downloads
+/// the source code and scans it with MythX. At most `limit` analyses are submitted, 0 means
+/// no limit.
+pub struct Pipeline<'a> {
+    setting: &'a settings::Settings,
+    chain: &'a Chain,
+    explorer: explorer::Explorer,
+    mythx: Option<mythx::MythX>,
+    db: db::Db,
+    pool: Pool,
+    limit: usize,
+    scanned: usize,
+}
+
+impl<'a> Pipeline<'a> {
+    pub async fn new(
+        setting: &'a settings::Settings,
+        chain: &'a Chain,
+        pool: &Pool,
+        limiters: &Limiters,
+        limit: usize,
+    ) -> Self {
+        let db = db::Db::from_settings(setting).unwrap_or_else(|err| {
+            println!("Error: Couldn't open database \n{}", err);
+            std::process::exit(1);
+        });
+
+        Pipeline {
+            setting,
+            chain,
+            explorer: explorer::Explorer::for_chain(chain, &setting.scan.key)
+                .with_rate_limit(limiters.explorer.clone()),
+            mythx: connect_mythx(setting, limiters.mythx.clone()).await,
+            db,
+            pool: pool.clone(),
+            limit,
+            scanned: 0,
+        }
+    }
+
+    pub async fn process(&mut self, contract: &Contract) {
+        let contract_id = match self.store(contract) {
+            Ok(id) => id,
+            Err(e) => {
+                println!(
+                    "Couldn't store {}, skipping. \nError: {}",
+                    contract.address, e
+                );
+                return;
+            }
+        };
+
+        let code_address = self.code_address(contract_id, contract).await;
+        let dirs = self.grab_source(contract_id, &code_address).await;
+
+        if self.limit != 0 && self.scanned >= self.limit {
+            return;
+        }
+        if let Some(dir) = dirs.first() {
+            if self.mythx.is_some() {
+                self.scan_source(contract_id, &code_address, dir).await;
+                self.scanned += 1;
+            }
+        }
+    }
+
+    fn store(&self, contract: &Contract) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let id = self
+            .db
+            .upsert_contract(&self.chain.name, &contract.address)?;
+        self.db.record_balance(
+            id,
+            &self.chain.native_symbol,
+            self.chain.decimals,
+            contract.balance,
+            contract.value,
+            &contract.tokens,
+        )?;
+        Ok(id)
+    }
+
+    /// Returns the address of the code to analyze, the implementation when the contract is a
+    /// proxy. The implementation, admin and beacon are stored with the proxy, the value stays
+    /// with the proxy.
+    async fn code_address(&self, contract_id: i64, contract: &Contract) -> Address {
+        match proxy::resolve(&self.pool, &contract.address, contract.kind).await {
+            Ok(Some(proxy)) => {
+                println!(
+                    "{} is a proxy for {}",
+                    contract.address, proxy.implementation
+                );
+                if let Err(e) = self.db.set_proxy(contract_id, &proxy) {
+                    println!(
+                        "Couldn't store implementation of {} \nError: {}",
+                        contract.address, e
+                    );
+                }
+                proxy.implementation
+            }
+            Ok(None) => contract.address,
+            Err(e) => {
+                println!(
+                    "Couldn't see if {} is a proxy, analyzing it as is. \nError: {}",
+                    contract.address, e
+                );
+                contract.address
+            }
+        }
+    }
+
+    /// Downloads the verified source code of a contract to the storage folder, together with
+    /// its schema, and returns the directories that were written
+    async fn grab_source(&self, contract_id: i64, address: &Address) -> Vec<PathBuf> {
+        let root = Path::new(&self.setting.storage.file_path);
+        let dirs = match self
+            .explorer
+            .download(root, &self.chain.name, address)
+            .await
+        {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                println!(
+                    "Couldn't grab source code of {}, skipping. \nError: {}",
+                    address, e
+                );
+                return Vec::new();
+            }
+        };
+
+        match dirs.first() {
+            None => println!("{} isn't verified, skipping", address),
+            Some(dir) => {
+                if let Err(e) = self.save_source(contract_id, dir) {
+                    println!("Couldn't store source of {} \nError: {}", address, e);
+                }
+            }
+        }
+        for dir in &dirs {
+            println!("Source code written to {}", dir.display());
+            match schema::write_schema(dir) {
+                Ok(schema) => println!(
+                    "Schema of {} written, {} entry points",
+                    schema.contract,
+                    schema.entry_points.len()
+                ),
+                Err(e) => println!("Couldn't build schema of {} \nError: {}", address, e),
+            }
+        }
+        dirs
+    }
+
+    fn save_source(
+        &self,
+        contract_id: i64,
+        dir: &Path,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let metadata: explorer::Metadata =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("metadata.json"))?)?;
+        self.db.save_source(contract_id, dir, &metadata)
+    }
+
+    /// Scans the downloaded source code of a contract with MythX, prints the issues, stores
+    /// them and writes them to issues.json next to the source code
+    async fn scan_source(&mut self, contract_id: i64, address: &Address, dir: &Path) {
+        let mythx = match &self.mythx {
+            Some(mythx) => mythx,
+            None => return,
+        };
+
+        let input = async {
+            let bytecode = self
+                .pool
+                .request(|url| async move { jsonrpc::get_code(address, &url).await })
+                .await?;
+            mythx::AnalysisInput::from_dir(dir, &bytecode)
+        }
+        .await;
+        let input = match input {
+            Ok(input) => input,
+            Err(e) => {
+                println!("Couldn't scan {}, skipping. \nError: {}", address, e);
+                return;
+            }
+        };
+
+        let uuid = match mythx.submit(&input).await {
+            Ok(uuid) => uuid,
+            Err(e) => {
+                println!(
+                    "Couldn't submit {} to MythX, skipping. \nError: {}",
+                    address, e
+                );
+                return;
+            }
+        };
+        println!("Submitted {} to MythX, analysis {}", address, uuid);
+        let job_id = self.db.start_scan(contract_id, &uuid).ok();
+
+        let result = async {
+            mythx.wait(&uuid).await?;
+            mythx.issues(&uuid, &input).await
+        }
+        .await;
+
+        let issues = match result {
+            Ok(issues) => issues,
+            Err(e) => {
+                println!("Analysis of {} failed. \nError: {}", address, e);
+                if let Some(job_id) = job_id {
+                    let _ = self.db.fail_scan(job_id, &e.to_string());
+                }
+                return;
+            }
+        };
+
+        println!("{} issues found in {}", issues.len(), address);
+        for issue in &issues {
+            println!("{}", issue);
+        }
+
+        if let Some(job_id) = job_id {
+            if let Err(e) = self.db.finish_scan(job_id, &issues) {
+                println!("Couldn't store issues of {} \nError: {}", address, e);
+            }
+        }
+        let json = serde_json::to_string_pretty(&issues).unwrap_or_default();
+        if let Err(e) = std::fs::write(dir.join("issues.json"), json) {
+            println!("Couldn't write issues of {} \nError: {}", address, e);
+        }
+    }
+}
+
+/// Returns an authenticated MythX client, or None when there's no key or it's invalid
+async fn connect_mythx(setting: &settings::Settings, limiter: RateLimiter) -> Option<mythx::MythX> {
+    if setting.mythx.key.is_empty() {
+        println!("No MythX key configured, contracts won't be scanned");
+        return None;
+    }
+
+    let mut mythx = mythx::MythX::new(mythx::API_URL, &setting.mythx.key).with_rate_limit(limiter);
+    match mythx.authenticate().await {
+        Ok(()) => Some(mythx),
+        Err(e) => {
+            println!(
+                "Couldn't authenticate with MythX, contracts won't be scanned. \nError: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mythx::tests::{client, mock_mythx, SOURCE, UUID};
+    use serde_json::json;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const VAULT: &str = "0x1111111111111111111111111111111111111111";
+
+    #[tokio::test]
+    async fn test_scan_source() {
+        let mythx_server = MockServer::start().await;
+        mock_mythx(&mythx_server, "API_KEY").await;
+        let node = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "jsonrpc": "2.0", "id": 1, "result": "0x6080" })),
+            )
+            .mount(&node)
+            .await;
+
+        let dir = std::env::temp_dir().join(format!("merter_pipeline_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Vault.sol"), SOURCE).unwrap();
+        let metadata = explorer::Metadata {
+            address: VAULT.parse().unwrap(),
+            name: "Vault".to_string(),
+            compiler_version: "v0.8.4+commit.c7e474f2".to_string(),
+            optimization_used: true,
+            runs: 200,
+            evm_version: "Default".to_string(),
+            implementation: None,
+        };
+        std::fs::write(
+            dir.join("metadata.json"),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .unwrap();
+
+        let setting: settings::Settings = toml::from_str(&format!(
+            "[storage]\ndb_url = \"sqlite::memory:\"\nfile_path = {:?}\n[jsonrpc]\n[scan]\nkey = \"\"\n[mythx]\nkey = \"API_KEY\"\n",
+            dir.to_string_lossy()
+        ))
+        .unwrap();
+        let chain = &crate::chain::builtin()[0];
+        let mut mythx = client(&mythx_server, "API_KEY");
+        mythx.authenticate().await.unwrap();
+        let mut pipeline = Pipeline {
+            setting: &setting,
+            chain,
+            explorer: explorer::Explorer::for_chain(chain, ""),
+            mythx: Some(mythx),
+            db: db::Db::open("sqlite::memory:").unwrap(),
+            pool: Pool::single(&node.uri()),
+            limit: 0,
+            scanned: 0,
+        };
+
+        let address: Address = VAULT.parse().unwrap();
+        let contract_id = pipeline.db.upsert_contract(&chain.name, &address).unwrap();
+        pipeline.scan_source(contract_id, &address, &dir).await;
+
+        let (uuid, status): (String, String) = pipeline
+            .db
+            .connection()
+            .query_row("SELECT uuid, status FROM scan_jobs", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((uuid.as_str(), status.as_str()), (UUID, "finished"));
+        let swc_id: String = pipeline
+            .db
+            .connection()
+            .query_row(
+                "SELECT swc_id FROM issues WHERE contract_id = ?1",
+                [contract_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(swc_id, "SWC-107");
+
+        let issues: Vec<crate::issue::Issue> =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("issues.json")).unwrap())
+                .unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].locations[0].line, Some(3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,529 @@
+use super::chain::Chain;
+use super::jsonrpc::{self, Error, ErrorKind};
+use super::ratelimit::RateLimiter;
+use super::settings;
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Weight of the newest sample in the latency and error rate averages
+const SMOOTHING: f64 = 0.2;
+/// Consecutive failures after which an endpoint is put on a cooldown
+const MAX_FAILURES: u32 = 3;
+/// First cooldown, it doubles with every failure after that up to MAX_COOLDOWN
+const COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+/// Break given to an endpoint that rate limits us, it doesn't count as a failure
+const RATE_LIMITED_COOLDOWN: Duration = Duration::from_secs(1);
+
+/// Pool of json-rpc endpoints. Requests are spread over the endpoints by weight, corrected for
+/// their latency and error rate, and endpoints that keep failing are skipped until their
+/// cooldown ends. Clones share the same endpoints and statistics.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    endpoints: Vec<Endpoint>,
+    health: Mutex<Vec<Health>>,
+    attempts: usize,
+}
+
+struct Endpoint {
+    url: String,
+    weight: f64,
+    limiter: RateLimiter,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Smooth weighted round robin counter
+    current: f64,
+    latency: Option<f64>,
+    error_rate: f64,
+    failures: u32,
+    cooldown_until: Option<Instant>,
+}
+
+/// Represents the health of an endpoint as seen by the pool
+#[derive(Debug, PartialEq)]
+pub struct EndpointStatus {
+    pub url: String,
+    pub available: bool,
+    pub latency: Option<Duration>,
+    pub error_rate: f64,
+}
+
+impl Pool {
+    /// Creates a pool of the endpoints, fails when there are none
+    pub fn new(endpoints: &[settings::Endpoint]) -> Result<Self, String> {
+        if endpoints.is_empty() {
+            return Err("no json-rpc endpoints configured".to_string());
+        }
+        let endpoints: Vec<Endpoint> = endpoints
+            .iter()
+            .map(|endpoint| Endpoint {
+                url: endpoint.url.clone(),
+                weight: f64::from(endpoint.weight.max(1)),
+                limiter: RateLimiter::from_settings(&endpoint.rate_limit),
+            })
+            .collect();
+
+        Ok(Pool {
+            inner: Arc::new(Inner {
+                health: Mutex::new(endpoints.iter().map(|_| Health::default()).collect()),
+                attempts: std::cmp::max(3, endpoints.len()),
+                endpoints,
+            }),
+        })
+    }
+
+    pub fn from_settings(jsonrpc: &settings::JsonRpc) -> Result<Self, String> {
+        Pool::new(&jsonrpc.endpoints())
+    }
+
+    /// Creates a pool of the endpoints that serve chain. Every endpoint is asked for its chain
+    /// id first, endpoints serving another chain are dropped and it fails when none is left.
+    /// Endpoints that can't be asked are kept, they're skipped like any failing endpoint.
+    pub async fn for_chain(jsonrpc: &settings::JsonRpc, chain: &Chain) -> Result<Self, String> {
+        let endpoints = jsonrpc.endpoints();
+        if endpoints.is_empty() {
+            return Pool::new(&endpoints);
+        }
+
+        let mut serving = Vec::new();
+        let mut wrong = Vec::new();
+        for endpoint in endpoints {
+            match jsonrpc::get_chain_id(&endpoint.url).await {
+                Ok(id) if id == chain.chain_id => serving.push(endpoint),
+                Ok(id) => {
+                    println!(
+                        "Dropping {}, it serves chain id {} instead of {} ({})",
+                        endpoint.url, id, chain.chain_id, chain
+                    );
+                    wrong.push(format!("{} serves chain id {}", endpoint.url, id));
+                }
+                Err(e) => {
+                    println!(
+                        "Couldn't check which chain {} serves, keeping it. \nError: {}",
+                        endpoint.url, e
+                    );
+                    serving.push(endpoint);
+                }
+            }
+        }
+
+        if serving.is_empty() {
+            return Err(format!(
+                "none of the json-rpc endpoints serve {} (chain id {}), {}",
+                chain,
+                chain.chain_id,
+                wrong.join(", ")
+            ));
+        }
+        Pool::new(&serving)
+    }
+
+    /// Creates a pool of one endpoint without a rate limit
+    pub fn single(url: &str) -> Self {
+        Pool::new(&[settings::Endpoint::new(url)]).unwrap()
+    }
+
+    /// Sends a request to the best endpoint. What happens on failure depends on the kind of
+    /// error: requests that are wrong, revert or ask too much are returned right away,
+    /// endpoints that rate limit get a short break and endpoints that are down count towards
+    /// their cooldown, both are retried on the next best endpoint, after waiting out the break
+    /// when there's none. A request for pruned state is tried on every endpoint once. Every
+    /// endpoint is tried once before one is tried again. Returns the last error when all
+    /// attempts failed.
+    pub async fn request<T, F, Fut>(&self, mut send: F) -> Result<T, Error>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut tried = Vec::new();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if tried.len() == self.inner.endpoints.len() {
+                tried.clear();
+            }
+            let (index, cooldown_until) = self.pick(&tried);
+            tried.push(index);
+            if let Some(until) = cooldown_until {
+                tokio::time::sleep_until(until).await;
+            }
+
+            let endpoint = &self.inner.endpoints[index];
+            endpoint.limiter.acquire().await;
+            let start = Instant::now();
+            match send(endpoint.url.clone()).await {
+                Ok(v) => {
+                    self.report(index, Some(start.elapsed()));
+                    return Ok(v);
+                }
+                Err(e) => {
+                    match e.kind() {
+                        ErrorKind::InvalidRequest | ErrorKind::Reverted | ErrorKind::TooLarge => {
+                            return Err(e)
+                        }
+                        ErrorKind::PrunedState if tried.len() == self.inner.endpoints.len() => {
+                            return Err(e)
+                        }
+                        ErrorKind::PrunedState => {}
+                        ErrorKind::RateLimited => self.back_off(index),
+                        ErrorKind::Unavailable => self.report(index, None),
+                    }
+                    if attempt >= self.inner.attempts {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the index of the endpoint the next request goes to. Endpoints on a cooldown
+    /// and the ones in `exclude` are skipped, unless there's nothing else left, then the one
+    /// that comes off its cooldown first is used and the end of its cooldown is returned with
+    /// it, the request has to wait until then.
+    fn pick(&self, exclude: &[usize]) -> (usize, Option<Instant>) {
+        let mut health = self.inner.health.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let available = |i: usize, health: &Health| {
+            !exclude.contains(&i) && health.cooldown_until.is_none_or(|until| until <= now)
+        };
+
+        let fastest = health
+            .iter()
+            .filter_map(|h| h.latency)
+            .fold(f64::INFINITY, f64::min);
+        let weights: Vec<Option<f64>> = health
+            .iter()
+            .enumerate()
+            .map(|(i, h)| {
+                if !available(i, h) {
+                    return None;
+                }
+                let reliability = (1.0 - h.error_rate).max(0.05);
+                let speed = match h.latency {
+                    Some(latency) if latency > 0.0 && fastest.is_finite() => fastest / latency,
+                    _ => 1.0,
+                };
+                Some(self.inner.endpoints[i].weight * reliability * speed)
+            })
+            .collect();
+
+        let total: f64 = weights.iter().flatten().sum();
+        if total == 0.0 {
+            let index = (0..health.len())
+                .filter(|i| !exclude.contains(i))
+                .min_by_key(|&i| health[i].cooldown_until)
+                .unwrap_or(0);
+            let until = health[index].cooldown_until.filter(|&until| until > now);
+            return (index, until);
+        }
+
+        let mut best = None;
+        for (i, weight) in weights.iter().enumerate() {
+            if let Some(weight) = weight {
+                health[i].current += weight;
+                if best.is_none_or(|b: usize| health[i].current > health[b].current) {
+                    best = Some(i);
+                }
+            }
+        }
+        let best = best.unwrap_or(0);
+        health[best].current -= total;
+        (best, None)
+    }
+
+    /// Records the outcome of a request, None is a failure
+    fn report(&self, index: usize, latency: Option<Duration>) {
+        let mut health = self.inner.health.lock().unwrap_or_else(|e| e.into_inner());
+        let health = &mut health[index];
+        match latency {
+            Some(latency) => {
+                let secs = latency.as_secs_f64();
+                health.latency = Some(match health.latency {
+                    Some(avg) => avg + SMOOTHING * (secs - avg),
+                    None => secs,
+                });
+                health.error_rate -= SMOOTHING * health.error_rate;
+                health.failures = 0;
+                health.cooldown_until = None;
+            }
+            None => {
+                health.error_rate += SMOOTHING * (1.0 - health.error_rate);
+                health.failures += 1;
+                if health.failures >= MAX_FAILURES {
+                    let cooldown = COOLDOWN
+                        .checked_mul(1 << (health.failures - MAX_FAILURES).min(16))
+                        .map_or(MAX_COOLDOWN, |c| c.min(MAX_COOLDOWN));
+                    health.cooldown_until = Some(Instant::now() + cooldown);
+                    println!(
+                        "{} failed {} times in a row, skipping it for {}s",
+                        self.inner.endpoints[index].url,
+                        health.failures,
+                        cooldown.as_secs()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Skips an endpoint that rate limits us for a moment
+    fn back_off(&self, index: usize) {
+        let mut health = self.inner.health.lock().unwrap_or_else(|e| e.into_inner());
+        let until = Instant::now() + RATE_LIMITED_COOLDOWN;
+        health[index].cooldown_until = health[index].cooldown_until.max(Some(until));
+    }
+
+    /// Returns the health of every endpoint
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        let health = self.inner.health.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        self.inner
+            .endpoints
+            .iter()
+            .zip(health.iter())
+            .map(|(endpoint, health)| EndpointStatus {
+                url: endpoint.url.clone(),
+                available: health.cooldown_until.is_none_or(|until| until <= now),
+                latency: health.latency.map(Duration::from_secs_f64),
+                error_rate: health.error_rate,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::jsonrpc;
+    use super::*;
+    use std::collections::HashMap;
+
+    fn endpoint(url: &str, weight: u32) -> settings::Endpoint {
+        settings::Endpoint {
+            weight,
+            ..settings::Endpoint::new(url)
+        }
+    }
+
+    async fn count_requests(pool: &Pool, requests: usize) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for _ in 0..requests {
+            let url = pool.request(|url| async move { Ok(url) }).await;
+            *counts.entry(url.unwrap()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_spreads_by_weight() {
+        let pool = Pool::new(&[endpoint("a", 3), endpoint("b", 1)]).unwrap();
+        let counts = count_requests(&pool, 8).await;
+        assert_eq!(counts["a"], 6);
+        assert_eq!(counts["b"], 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fails_over_and_cools_down() {
+        let pool = Pool::new(&[endpoint("bad", 1), endpoint("good", 1)]).unwrap();
+        let send = |url: String| async move {
+            if url == "bad" {
+                Err(Error::Status(502))
+            } else {
+                Ok(url)
+            }
+        };
+
+        for _ in 0..6 {
+            assert_eq!(pool.request(send).await.unwrap(), "good");
+        }
+        let status = pool.status();
+        assert!(!status[0].available);
+        assert!(status[0].error_rate > 0.4);
+        assert!(status[1].available);
+        assert_eq!(status[1].error_rate, 0.0);
+
+        // While bad is cooling down everything goes to good
+        let counts = count_requests(&pool, 4).await;
+        assert_eq!(counts.get("bad"), None);
+
+        // After the cooldown bad gets another chance and recovers
+        tokio::time::advance(COOLDOWN).await;
+        assert!(pool.status()[0].available);
+        let counts = count_requests(&pool, 10).await;
+        assert!(counts["bad"] > 0);
+        assert!(pool.status()[0].available);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_all_failing() {
+        let pool = Pool::new(&[endpoint("a", 1), endpoint("b", 1)]).unwrap();
+        let mut calls = Vec::new();
+        let result: Result<(), Error> = pool
+            .request(|url| {
+                calls.push(url.clone());
+                async move { Err(Error::Decode(url)) }
+            })
+            .await;
+        // Both endpoints are tried before one is retried, the last error is returned
+        assert_eq!(calls.len(), 3);
+        assert_ne!(calls[0], calls[1]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!("invalid response: {}", calls[2])
+        );
+
+        // Everything is on a cooldown, the endpoint that comes off first is used once it does
+        for _ in 0..3 {
+            let _: Result<(), Error> = pool.request(|_| async { Err(Error::Status(502)) }).await;
+        }
+        assert!(pool.status().iter().all(|s| !s.available));
+        let first_free = {
+            let health = pool.inner.health.lock().unwrap();
+            health
+                .iter()
+                .filter_map(|h| h.cooldown_until)
+                .min()
+                .unwrap()
+        };
+        let mut sent_at = None;
+        let result = pool
+            .request(|url| {
+                sent_at = Some(Instant::now());
+                async move { Ok(url) }
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(sent_at, Some(first_free));
+    }
+
+    fn rpc_error(code: i64, message: &str) -> Error {
+        Error::Rpc(jsonrpc::RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_by_error_kind() {
+        let pool = Pool::new(&[endpoint("a", 1), endpoint("b", 1), endpoint("c", 1)]).unwrap();
+        let calls = |error: fn() -> Error| {
+            let pool = pool.clone();
+            async move {
+                let mut calls = 0;
+                let result: Result<(), Error> = pool
+                    .request(|_| {
+                        calls += 1;
+                        async move { Err(error()) }
+                    })
+                    .await;
+                assert!(result.is_err());
+                calls
+            }
+        };
+
+        // Every node answers the same to a wrong or reverting request
+        assert_eq!(calls(|| rpc_error(-32602, "invalid argument")).await, 1);
+        assert_eq!(calls(|| rpc_error(3, "execution reverted")).await, 1);
+        // Pruned state is asked from every node once, without hurting their health
+        assert_eq!(calls(|| rpc_error(-32000, "missing trie node")).await, 3);
+        assert!(pool
+            .status()
+            .iter()
+            .all(|s| s.available && s.error_rate == 0.0));
+
+        // Rate limited endpoints get a break, but their health doesn't suffer
+        assert_eq!(calls(|| Error::Status(429)).await, 3);
+        assert!(pool
+            .status()
+            .iter()
+            .all(|s| !s.available && s.error_rate == 0.0));
+        tokio::time::advance(RATE_LIMITED_COOLDOWN).await;
+        assert!(pool.status().iter().all(|s| s.available));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limited_single_endpoint_waits() {
+        let pool = Pool::single("a");
+        let mut sent_at = Vec::new();
+        let result: Result<(), Error> = pool
+            .request(|_| {
+                sent_at.push(Instant::now());
+                async { Err(Error::Status(429)) }
+            })
+            .await;
+        assert!(result.is_err());
+        // The only endpoint gets its break before every retry
+        assert_eq!(sent_at.len(), 3);
+        for pair in sent_at.windows(2) {
+            assert!(pair[1] - pair[0] >= RATE_LIMITED_COOLDOWN);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prefers_fast_endpoints() {
+        let pool = Pool::new(&[endpoint("slow", 1), endpoint("fast", 1)]).unwrap();
+        let send = |url: String| async move {
+            let delay = if url == "slow" { 400 } else { 100 };
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            Ok(url)
+        };
+        let mut counts = HashMap::new();
+        for _ in 0..20 {
+            *counts.entry(pool.request(send).await.unwrap()).or_insert(0) += 1;
+        }
+        assert!(counts["fast"] >= 3 * counts["slow"]);
+        assert_eq!(pool.status()[1].latency, Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_needs_endpoints() {
+        assert!(Pool::new(&[]).is_err());
+    }
+
+    async fn serving(chain_id: &str) -> wiremock::MockServer {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": chain_id }),
+            ))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_for_chain_drops_other_chains() {
+        let eth = serving("0x1").await;
+        let bsc = serving("0x38").await;
+        let jsonrpc = settings::JsonRpc {
+            endpoints: vec![
+                settings::Endpoint::new(&bsc.uri()),
+                settings::Endpoint::new(&eth.uri()),
+            ],
+            ..Default::default()
+        };
+        let chains = crate::chain::builtin();
+
+        let pool = Pool::for_chain(&jsonrpc, &chains[0]).await.unwrap();
+        let status = pool.status();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].url, eth.uri());
+
+        let only_bsc = settings::JsonRpc {
+            endpoints: vec![settings::Endpoint::new(&bsc.uri())],
+            ..Default::default()
+        };
+        let err = Pool::for_chain(&only_bsc, &chains[0]).await.err().unwrap();
+        assert!(err.contains("serves chain id 56"), "{}", err);
+    }
+}
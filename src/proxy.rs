@@ -0,0 +1,295 @@
+use super::address::Address;
+use super::code::CodeKind;
+use super::jsonrpc;
+use super::pool::Pool;
+
+/// EIP-1967 storage slots, keccak256 of the name minus one
+const IMPLEMENTATION_SLOT: &str =
+    "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+const ADMIN_SLOT: &str = "0xb53127684a568b3173ae13b9f8a6016e243e63b6e8ee1178d6a717850b5d6103";
+const BEACON_SLOT: &str = "0xa3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50";
+
+/// Slots of the OpenZeppelin proxies from before EIP-1967, keccak256 of the name
+const LEGACY_IMPLEMENTATION_SLOT: &str =
+    "0x7050c9e0f4ca769c69bd3a8ef740bc37934f8e2c036e5a723fd8ee048ed3f8c3";
+const LEGACY_ADMIN_SLOT: &str =
+    "0x10d6a54a4754c8869d6886b5f5d7fbfa5b4522237ea5c60d11bc4e7a1ff9390b";
+
+/// implementation(), asked from a beacon
+const IMPLEMENTATION: &str = "0x5c60da1b";
+
+/// Proxies pointing to proxies are followed this many times
+const MAX_HOPS: usize = 5;
+
+/// Represents a proxy resolved to the contract that runs its code. Admin and beacon are
+/// those of the proxy itself, not of proxies further down the chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proxy {
+    pub implementation: Address,
+    pub admin: Option<Address>,
+    pub beacon: Option<Address>,
+}
+
+/// Returns the implementation behind a proxy, None when the address isn't a proxy. A proxy
+/// of which the implementation is a proxy again is followed to the end of the chain.
+pub async fn resolve(
+    pool: &Pool,
+    address: &Address,
+    kind: CodeKind,
+) -> Result<Option<Proxy>, jsonrpc::Error> {
+    let mut resolved: Option<Proxy> = None;
+    let mut current = *address;
+    let mut kind = kind;
+
+    for _ in 0..MAX_HOPS {
+        let hop = match target(pool, &current, kind).await? {
+            Some(hop) => hop,
+            None => break,
+        };
+        let next = hop.implementation;
+        if next == current || next == *address {
+            break;
+        }
+
+        let code = pool
+            .request(|url| async move { jsonrpc::get_code(&next, &url).await })
+            .await?;
+        kind = CodeKind::classify(&next, &code);
+        current = next;
+        match &mut resolved {
+            Some(proxy) => proxy.implementation = next,
+            None => resolved = Some(hop),
+        }
+        if !kind.is_contract() {
+            break;
+        }
+    }
+    Ok(resolved)
+}
+
+/// Returns where a single proxy points to
+async fn target(
+    pool: &Pool,
+    address: &Address,
+    kind: CodeKind,
+) -> Result<Option<Proxy>, jsonrpc::Error> {
+    if let CodeKind::MinimalProxy(implementation) = kind {
+        return Ok(Some(Proxy {
+            implementation,
+            admin: None,
+            beacon: None,
+        }));
+    }
+    if !kind.is_contract() {
+        return Ok(None);
+    }
+
+    if let Some(implementation) = slot(pool, address, IMPLEMENTATION_SLOT).await? {
+        return Ok(Some(Proxy {
+            implementation,
+            admin: slot(pool, address, ADMIN_SLOT).await?,
+            beacon: None,
+        }));
+    }
+    if let Some(beacon) = slot(pool, address, BEACON_SLOT).await? {
+        let word = pool
+            .request(|url| async move { jsonrpc::eth_call(&beacon, IMPLEMENTATION, &url).await })
+            .await?;
+        return Ok(Address::from_word(&word)
+            .filter(|a| *a != Address::zero())
+            .map(|implementation| Proxy {
+                implementation,
+                admin: None,
+                beacon: Some(beacon),
+            }));
+    }
+    if let Some(implementation) = slot(pool, address, LEGACY_IMPLEMENTATION_SLOT).await? {
+        return Ok(Some(Proxy {
+            implementation,
+            admin: slot(pool, address, LEGACY_ADMIN_SLOT).await?,
+            beacon: None,
+        }));
+    }
+    Ok(None)
+}
+
+/// Returns the address stored in a slot, None when it's empty or holds something else
+async fn slot(
+    pool: &Pool,
+    address: &Address,
+    slot: &str,
+) -> Result<Option<Address>, jsonrpc::Error> {
+    let word = pool
+        .request(|url| async move { jsonrpc::get_storage_at(address, slot, &url).await })
+        .await?;
+    Ok(Address::from_word(&word).filter(|a| *a != Address::zero()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitive_types::U256;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tiny_keccak::{Hasher, Keccak};
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    const PROXY: &str = "0x1111111111111111111111111111111111111111";
+    const IMPL: &str = "0x2222222222222222222222222222222222222222";
+    const ADMIN: &str = "0x3333333333333333333333333333333333333333";
+    const BEACON: &str = "0x4444444444444444444444444444444444444444";
+    const CLONE: &str = "0x5555555555555555555555555555555555555555";
+
+    fn address(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
+    fn word(s: &str) -> String {
+        format!("0x{}", address(s).to_word())
+    }
+
+    fn keccak(name: &str) -> U256 {
+        let mut keccak = Keccak::v256();
+        keccak.update(name.as_bytes());
+        let mut hash = [0u8; 32];
+        keccak.finalize(&mut hash);
+        U256::from_big_endian(&hash)
+    }
+
+    #[test]
+    fn test_slots() {
+        let slot = |s: &str| U256::from_str_radix(&s[2..], 16).unwrap();
+        let eip1967 = |name: &str| keccak(name) - 1;
+        assert_eq!(
+            slot(IMPLEMENTATION_SLOT),
+            eip1967("eip1967.proxy.implementation")
+        );
+        assert_eq!(slot(ADMIN_SLOT), eip1967("eip1967.proxy.admin"));
+        assert_eq!(slot(BEACON_SLOT), eip1967("eip1967.proxy.beacon"));
+        assert_eq!(
+            slot(LEGACY_IMPLEMENTATION_SLOT),
+            keccak("org.zeppelinos.proxy.implementation")
+        );
+        assert_eq!(
+            slot(LEGACY_ADMIN_SLOT),
+            keccak("org.zeppelinos.proxy.admin")
+        );
+    }
+
+    /// Serves the code and storage of a set of contracts, storage that isn't set is zero
+    async fn mock_chain(
+        code: HashMap<&'static str, String>,
+        storage: HashMap<(&'static str, &'static str), String>,
+    ) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(move |request: &Request| {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                let params = &body["params"];
+                let result = match body["method"].as_str().unwrap() {
+                    "eth_getCode" => code
+                        .iter()
+                        .find(|(a, _)| **a == params[0])
+                        .map_or("0x".to_string(), |(_, c)| c.clone()),
+                    "eth_getStorageAt" => storage
+                        .iter()
+                        .find(|((a, s), _)| *a == params[0] && *s == params[1])
+                        .map_or(format!("0x{:064x}", 0), |(_, w)| w.clone()),
+                    // Only beacons are called
+                    "eth_call" => word(IMPL),
+                    other => panic!("unexpected {}", other),
+                };
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "jsonrpc": "2.0", "id": 1, "result": result }))
+            })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn code() -> HashMap<&'static str, String> {
+        let mut code = HashMap::new();
+        code.insert(PROXY, "0x6080".to_string());
+        code.insert(IMPL, "0x6080".to_string());
+        code.insert(
+            CLONE,
+            format!(
+                "0x363d3d373d3d3d363d73{}5af43d82803e903d91602b57fd5bf3",
+                &PROXY[2..]
+            ),
+        );
+        code
+    }
+
+    #[tokio::test]
+    async fn test_resolve_eip1967() {
+        let mut storage = HashMap::new();
+        storage.insert((PROXY, IMPLEMENTATION_SLOT), word(IMPL));
+        storage.insert((PROXY, ADMIN_SLOT), word(ADMIN));
+        let server = mock_chain(code(), storage).await;
+        let pool = Pool::single(&server.uri());
+
+        let proxy = resolve(&pool, &address(PROXY), CodeKind::Contract)
+            .await
+            .unwrap();
+        assert_eq!(
+            proxy,
+            Some(Proxy {
+                implementation: address(IMPL),
+                admin: Some(address(ADMIN)),
+                beacon: None,
+            })
+        );
+        assert_eq!(
+            resolve(&pool, &address(IMPL), CodeKind::Contract)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // A minimal proxy of the proxy ends up at the same implementation
+        let clone = resolve(
+            &pool,
+            &address(CLONE),
+            CodeKind::MinimalProxy(address(PROXY)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(clone.implementation, address(IMPL));
+        assert_eq!(clone.admin, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_beacon_and_legacy() {
+        let mut storage = HashMap::new();
+        storage.insert((PROXY, BEACON_SLOT), word(BEACON));
+        let server = mock_chain(code(), storage).await;
+        let proxy = resolve(
+            &Pool::single(&server.uri()),
+            &address(PROXY),
+            CodeKind::Contract,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(proxy.implementation, address(IMPL));
+        assert_eq!(proxy.beacon, Some(address(BEACON)));
+
+        let mut storage = HashMap::new();
+        storage.insert((PROXY, LEGACY_IMPLEMENTATION_SLOT), word(IMPL));
+        storage.insert((PROXY, LEGACY_ADMIN_SLOT), word(ADMIN));
+        let server = mock_chain(code(), storage).await;
+        let proxy = resolve(
+            &Pool::single(&server.uri()),
+            &address(PROXY),
+            CodeKind::Contract,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(proxy.implementation, address(IMPL));
+        assert_eq!(proxy.admin, Some(address(ADMIN)));
+    }
+}
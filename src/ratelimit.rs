@@ -0,0 +1,161 @@
+use super::settings;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Token bucket rate limiter for one endpoint. Clones share the same bucket, so every client
+/// talking to the endpoint draws from one budget. The bucket holds at most `burst` requests
+/// and refills at `requests_per_second`.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter that starts with a full bucket, a rate of 0 means unlimited and a
+    /// burst below 1 is raised to 1
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        if requests_per_second <= 0.0 || !requests_per_second.is_finite() {
+            return RateLimiter::unlimited();
+        }
+        let burst = f64::from(burst.max(1));
+        RateLimiter {
+            bucket: Some(Arc::new(Mutex::new(Bucket {
+                rate: requests_per_second,
+                burst,
+                tokens: burst,
+                last: Instant::now(),
+            }))),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        RateLimiter { bucket: None }
+    }
+
+    pub fn from_settings(limit: &settings::RateLimit) -> Self {
+        RateLimiter::new(limit.requests_per_second, limit.burst)
+    }
+
+    /// Waits until a request may be sent. Every caller reserves its token right away, so
+    /// concurrent callers are served in the order they called acquire.
+    pub async fn acquire(&self) {
+        let bucket = match &self.bucket {
+            Some(bucket) => bucket,
+            None => return,
+        };
+
+        let wait = {
+            let mut bucket = bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * bucket.rate).min(bucket.burst);
+            bucket.last = now;
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                Duration::from_secs(0)
+            } else {
+                Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+            }
+        };
+
+        if wait > Duration::from_secs(0) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Represents the limiters of the api's in a config file, the json-rpc endpoints have theirs
+/// in the pool
+#[derive(Debug, Clone)]
+pub struct Limiters {
+    pub explorer: RateLimiter,
+    pub mythx: RateLimiter,
+}
+
+impl Limiters {
+    pub fn from_settings(setting: &settings::Settings) -> Self {
+        Limiters {
+            explorer: RateLimiter::from_settings(&setting.scan.rate_limit),
+            mythx: RateLimiter::from_settings(&setting.mythx.rate_limit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn elapsed_after(limiter: &RateLimiter, requests: usize) -> Duration {
+        let start = Instant::now();
+        for _ in 0..requests {
+            limiter.acquire().await;
+        }
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_then_rate() {
+        let limiter = RateLimiter::new(10.0, 5);
+        assert_eq!(elapsed_after(&limiter, 5).await, Duration::from_secs(0));
+        assert_eq!(elapsed_after(&limiter, 10).await, Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refills_up_to_burst() {
+        let limiter = RateLimiter::new(2.0, 3);
+        elapsed_after(&limiter, 3).await;
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(elapsed_after(&limiter, 3).await, Duration::from_secs(0));
+        assert_eq!(elapsed_after(&limiter, 1).await, Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clones_share_budget() {
+        let limiter = RateLimiter::new(4.0, 1);
+        let other = limiter.clone();
+        let start = Instant::now();
+
+        let tasks: Vec<_> = (0..4)
+            .map(|i| {
+                let limiter = if i % 2 == 0 {
+                    limiter.clone()
+                } else {
+                    other.clone()
+                };
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(750));
+
+        // A separate endpoint has its own budget
+        let separate = RateLimiter::new(4.0, 1);
+        assert_eq!(elapsed_after(&separate, 1).await, Duration::from_secs(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unlimited() {
+        assert_eq!(
+            elapsed_after(&RateLimiter::new(0.0, 0), 1000).await,
+            Duration::from_secs(0)
+        );
+        assert_eq!(
+            elapsed_after(&RateLimiter::unlimited(), 1000).await,
+            Duration::from_secs(0)
+        );
+    }
+}
@@ -0,0 +1,881 @@
+use super::explorer::Metadata;
+use super::mythx;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tiny_keccak::{Hasher, Keccak};
+
+/// Solidity keywords that can appear in a function header without being a modifier
+const HEADER_KEYWORDS: [&str; 12] = [
+    "public",
+    "external",
+    "internal",
+    "private",
+    "pure",
+    "view",
+    "payable",
+    "nonpayable",
+    "constant",
+    "virtual",
+    "override",
+    "returns",
+];
+
+/// Represents a public or external function of a contract, taken from the abi
+#[derive(Debug, PartialEq, Serialize)]
+pub struct EntryPoint {
+    pub name: String,
+    pub signature: String,
+    pub selector: String,
+    pub mutability: String,
+    pub payable: bool,
+    pub modifiers: Vec<String>,
+    /// The call graph node of the function, None when it isn't found in the source code
+    pub node: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Function,
+    Modifier,
+    Constructor,
+    Fallback,
+    Receive,
+}
+
+/// Represents a function or modifier in the call graph, named Contract.function
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: Kind,
+    pub entry_point: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+/// Represents the entry-points of a contract and the internal flow between its functions.
+/// Overloaded functions share one node.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Schema {
+    pub contract: String,
+    pub entry_points: Vec<EntryPoint>,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AbiItem {
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    inputs: Vec<AbiParam>,
+    #[serde(default, rename = "stateMutability")]
+    state_mutability: String,
+    #[serde(default)]
+    constant: bool,
+    #[serde(default)]
+    payable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct AbiParam {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    components: Vec<AbiParam>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Punct(char),
+}
+
+#[derive(Debug)]
+enum Call {
+    Bare(String),
+    Super(String),
+    Qualified(String, String),
+}
+
+/// Represents a function or modifier with a body found in the source code
+#[derive(Debug)]
+struct Definition {
+    contract: String,
+    name: String,
+    kind: Kind,
+    params: usize,
+    public: bool,
+    modifiers: Vec<String>,
+    calls: Vec<Call>,
+}
+
+impl Definition {
+    fn id(&self) -> String {
+        if self.contract.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.contract, self.name)
+        }
+    }
+}
+
+/// Builds the schema of a directory written by explorer::write_source and writes it to
+/// schema.json and schema.dot in the same directory
+pub fn write_schema(dir: &Path) -> Result<Schema, Box<dyn Error + Send + Sync>> {
+    let metadata: Metadata =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("metadata.json"))?)?;
+    let abi = std::fs::read_to_string(dir.join("abi.json"))?;
+    let mut sources = BTreeMap::new();
+    mythx::read_sources(dir, dir, &mut sources)?;
+
+    let schema = build(&metadata.name, &abi, &sources)?;
+    std::fs::write(
+        dir.join("schema.json"),
+        serde_json::to_string_pretty(&schema)?,
+    )?;
+    std::fs::write(dir.join("schema.dot"), to_dot(&schema))?;
+    Ok(schema)
+}
+
+/// Builds the schema of contract `name` from its abi and source files
+pub fn build(
+    name: &str,
+    abi: &str,
+    sources: &BTreeMap<String, String>,
+) -> Result<Schema, Box<dyn Error + Send + Sync>> {
+    let abi: Vec<AbiItem> = serde_json::from_str(abi)?;
+
+    let mut bases = HashMap::new();
+    let mut defs = Vec::new();
+    for source in sources.values() {
+        let tokens = tokenize(source);
+        parse_block(&tokens, 0, tokens.len(), "", &mut bases, &mut defs);
+    }
+    let graph = Graph::new(&bases, &defs);
+
+    // The contract itself and everything it inherits, or all contracts when it isn't found
+    let scope = if bases.contains_key(name) {
+        graph.linearize(name)
+    } else {
+        let mut all: Vec<String> = bases.keys().cloned().collect();
+        all.sort();
+        all
+    };
+
+    let entry_points = abi
+        .iter()
+        .filter(|item| item.kind == "function" || item.kind.is_empty())
+        .map(|item| {
+            let def = scope
+                .iter()
+                .flat_map(|contract| graph.overloads(contract, &item.name))
+                .find(|&def| defs[def].params == item.inputs.len())
+                .or_else(|| {
+                    scope
+                        .iter()
+                        .flat_map(|contract| graph.lookup(contract, &item.name))
+                        .next()
+                });
+            let (mutability, payable) = mutability(item);
+            let signature = signature(item);
+            EntryPoint {
+                name: item.name.clone(),
+                selector: selector(&signature),
+                signature,
+                mutability,
+                payable,
+                modifiers: def
+                    .map(|def| {
+                        graph.modifiers[def]
+                            .iter()
+                            .map(|&m| defs[m].name.clone())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                node: def.map(|def| defs[def].id()),
+            }
+        })
+        .collect();
+
+    // Walk the graph from the entry points of the contract, the first definition of a name
+    // in the linearization overrides the others
+    let mut roots = Vec::new();
+    let mut seen_names = BTreeSet::new();
+    for contract in &scope {
+        for (i, def) in defs.iter().enumerate() {
+            if &def.contract == contract && def.public && seen_names.insert(def.name.clone()) {
+                roots.push(i);
+            }
+        }
+    }
+
+    let mut reached = BTreeSet::new();
+    let mut edges = BTreeSet::new();
+    let mut queue: VecDeque<usize> = roots.iter().cloned().collect();
+    while let Some(def) = queue.pop_front() {
+        if !reached.insert(def) {
+            continue;
+        }
+        for &target in graph.modifiers[def].iter().chain(graph.calls[def].iter()) {
+            edges.insert(Edge {
+                from: defs[def].id(),
+                to: defs[target].id(),
+            });
+            queue.push_back(target);
+        }
+    }
+
+    let mut nodes: BTreeMap<String, Node> = BTreeMap::new();
+    for &def in &reached {
+        let node = nodes.entry(defs[def].id()).or_insert(Node {
+            id: defs[def].id(),
+            kind: defs[def].kind,
+            entry_point: false,
+        });
+        node.entry_point |= roots.contains(&def);
+    }
+
+    Ok(Schema {
+        contract: name.to_string(),
+        entry_points,
+        nodes: nodes.into_values().collect(),
+        edges: edges.into_iter().collect(),
+    })
+}
+
+/// Renders the call graph as Graphviz DOT, entry-points are bold and modifiers are ellipses
+pub fn to_dot(schema: &Schema) -> String {
+    let mut dot = format!(
+        "digraph \"{}\" {{\n    rankdir=LR;\n    node [shape=box];\n",
+        schema.contract
+    );
+    for node in &schema.nodes {
+        let mut attributes = Vec::new();
+        if node.entry_point {
+            attributes.push("style=bold");
+        }
+        if node.kind == Kind::Modifier {
+            attributes.push("shape=ellipse");
+        }
+        if attributes.is_empty() {
+            dot.push_str(&format!("    \"{}\";\n", node.id));
+        } else {
+            dot.push_str(&format!(
+                "    \"{}\" [{}];\n",
+                node.id,
+                attributes.join(", ")
+            ));
+        }
+    }
+    for edge in &schema.edges {
+        dot.push_str(&format!("    \"{}\" -> \"{}\";\n", edge.from, edge.to));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Returns the 4 byte selector of a function signature, e.g. transfer(address,uint256)
+pub fn selector(signature: &str) -> String {
+    let mut keccak = Keccak::v256();
+    let mut hash = [0u8; 32];
+    keccak.update(signature.as_bytes());
+    keccak.finalize(&mut hash);
+    let hex: String = hash[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", hex)
+}
+
+fn signature(item: &AbiItem) -> String {
+    let types: Vec<String> = item.inputs.iter().map(canonical_type).collect();
+    format!("{}({})", item.name, types.join(","))
+}
+
+/// Expands tuples to their components, tuple[] becomes (address,uint256)[]
+fn canonical_type(param: &AbiParam) -> String {
+    match param.kind.strip_prefix("tuple") {
+        Some(suffix) => {
+            let components: Vec<String> = param.components.iter().map(canonical_type).collect();
+            format!("({}){}", components.join(","), suffix)
+        }
+        None => param.kind.clone(),
+    }
+}
+
+/// Returns the state mutability and payable flag, older abis only have constant and payable
+fn mutability(item: &AbiItem) -> (String, bool) {
+    let mutability = if !item.state_mutability.is_empty() {
+        item.state_mutability.clone()
+    } else if item.constant {
+        "view".to_string()
+    } else if item.payable {
+        "payable".to_string()
+    } else {
+        "nonpayable".to_string()
+    };
+    let payable = mutability == "payable";
+    (mutability, payable)
+}
+
+/// Resolves the calls and modifiers of the definitions to other definitions
+struct Graph<'a> {
+    bases: &'a HashMap<String, Vec<String>>,
+    by_name: HashMap<(&'a str, &'a str), Vec<usize>>,
+    calls: Vec<Vec<usize>>,
+    modifiers: Vec<Vec<usize>>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(bases: &'a HashMap<String, Vec<String>>, defs: &'a [Definition]) -> Self {
+        let mut by_name: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+        for (i, def) in defs.iter().enumerate() {
+            by_name
+                .entry((def.contract.as_str(), def.name.as_str()))
+                .or_default()
+                .push(i);
+        }
+
+        let mut graph = Graph {
+            bases,
+            by_name,
+            calls: Vec::new(),
+            modifiers: Vec::new(),
+        };
+        for def in defs {
+            let calls = def
+                .calls
+                .iter()
+                .filter_map(|call| graph.resolve(&def.contract, call))
+                .filter(|&target| defs[target].kind != Kind::Modifier)
+                .collect();
+            // Base constructor calls look like modifiers, they don't resolve to one
+            let modifiers = def
+                .modifiers
+                .iter()
+                .filter_map(|name| graph.resolve(&def.contract, &Call::Bare(name.clone())))
+                .filter(|&target| defs[target].kind == Kind::Modifier)
+                .collect();
+            graph.calls.push(calls);
+            graph.modifiers.push(modifiers);
+        }
+        graph
+    }
+
+    /// Returns the contract followed by its bases, most derived first
+    fn linearize(&self, contract: &str) -> Vec<String> {
+        let mut order = Vec::new();
+        let mut stack = vec![contract.to_string()];
+        while let Some(contract) = stack.pop() {
+            if order.contains(&contract) {
+                continue;
+            }
+            if let Some(bases) = self.bases.get(&contract) {
+                stack.extend(bases.iter().cloned());
+            }
+            order.push(contract);
+        }
+        order
+    }
+
+    fn overloads(&self, contract: &str, name: &str) -> Vec<usize> {
+        self.by_name
+            .get(&(contract, name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn lookup(&self, contract: &str, name: &str) -> Option<usize> {
+        self.by_name
+            .get(&(contract, name))
+            .and_then(|defs| defs.first().cloned())
+    }
+
+    fn resolve(&self, contract: &str, call: &Call) -> Option<usize> {
+        let (scope, name, skip) = match call {
+            Call::Bare(name) => (contract, name, 0),
+            Call::Super(name) => (contract, name, 1),
+            Call::Qualified(target, name) => {
+                if !self.bases.contains_key(target) {
+                    return None;
+                }
+                (target.as_str(), name, 0)
+            }
+        };
+        self.linearize(scope)
+            .iter()
+            .skip(skip)
+            .find_map(|c| self.lookup(c, name))
+            .or_else(|| self.lookup("", name))
+    }
+}
+
+/// Splits solidity source code into identifiers and punctuation, comments and string literals
+/// are dropped
+fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i += 1;
+        } else if c.is_alphanumeric() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            if !c.is_whitespace() {
+                tokens.push(Token::Punct(c));
+            }
+            i += 1;
+        }
+    }
+    tokens
+}
+
+fn is_ident(token: Option<&Token>, ident: &str) -> bool {
+    matches!(token, Some(Token::Ident(i)) if i == ident)
+}
+
+fn is_punct(token: Option<&Token>, punct: char) -> bool {
+    token == Some(&Token::Punct(punct))
+}
+
+/// Returns the index of the bracket closing the one at `open`
+fn closing(tokens: &[Token], open: usize, end: usize) -> usize {
+    let (opening, closing) = match tokens.get(open) {
+        Some(Token::Punct('(')) => ('(', ')'),
+        Some(Token::Punct('[')) => ('[', ']'),
+        _ => ('{', '}'),
+    };
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().take(end).skip(open) {
+        if token == &Token::Punct(opening) {
+            depth += 1;
+        } else if token == &Token::Punct(closing) {
+            depth -= 1;
+            if depth == 0 {
+                return i;
+            }
+        }
+    }
+    end
+}
+
+/// Collects the contracts and definitions between start and end, `contract` is empty at file
+/// level
+fn parse_block(
+    tokens: &[Token],
+    start: usize,
+    end: usize,
+    contract: &str,
+    bases: &mut HashMap<String, Vec<String>>,
+    defs: &mut Vec<Definition>,
+) {
+    let mut i = start;
+    while i < end {
+        let keyword = match &tokens[i] {
+            Token::Ident(keyword) => keyword.as_str(),
+            Token::Punct(_) => {
+                i += 1;
+                continue;
+            }
+        };
+        match keyword {
+            "contract" | "library" | "interface" if contract.is_empty() => {
+                let name = match tokens.get(i + 1) {
+                    Some(Token::Ident(name)) => name.clone(),
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                };
+                let mut j = i + 2;
+                let mut inherited = Vec::new();
+                while j < end && !is_punct(tokens.get(j), '{') {
+                    if is_punct(tokens.get(j), '(') {
+                        j = closing(tokens, j, end);
+                    } else if is_ident(tokens.get(j - 1), "is") || is_punct(tokens.get(j - 1), ',')
+                    {
+                        if let Token::Ident(base) = &tokens[j] {
+                            inherited.push(base.clone());
+                        }
+                    }
+                    j += 1;
+                }
+                let body_end = closing(tokens, j, end);
+                bases.insert(name.clone(), inherited);
+                parse_block(tokens, j + 1, body_end, &name, bases, defs);
+                i = body_end + 1;
+            }
+            "function" | "modifier" | "constructor" | "fallback" | "receive" => {
+                i = parse_definition(tokens, i, end, contract, defs);
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+/// Parses the definition starting at the keyword at `i` and returns the index after it
+fn parse_definition(
+    tokens: &[Token],
+    i: usize,
+    end: usize,
+    contract: &str,
+    defs: &mut Vec<Definition>,
+) -> usize {
+    let keyword = match &tokens[i] {
+        Token::Ident(keyword) => keyword.as_str(),
+        Token::Punct(_) => return i + 1,
+    };
+    let (kind, name, mut j) = match (keyword, tokens.get(i + 1)) {
+        ("function", Some(Token::Ident(name))) => (Kind::Function, name.clone(), i + 2),
+        // Fallback functions before solidity 0.6 have no name
+        ("function", Some(Token::Punct('('))) => (Kind::Fallback, "fallback".to_string(), i + 1),
+        ("modifier", Some(Token::Ident(name))) => (Kind::Modifier, name.clone(), i + 2),
+        ("constructor", Some(Token::Punct('('))) => (Kind::Constructor, keyword.to_string(), i + 1),
+        ("fallback", Some(Token::Punct('('))) => (Kind::Fallback, keyword.to_string(), i + 1),
+        ("receive", Some(Token::Punct('('))) => (Kind::Receive, keyword.to_string(), i + 1),
+        _ => return i + 1,
+    };
+
+    let mut params = 0;
+    if is_punct(tokens.get(j), '(') {
+        let close = closing(tokens, j, end);
+        if close > j + 1 {
+            params = 1 + tokens[j + 1..close]
+                .iter()
+                .scan(0, |depth, token| {
+                    match token {
+                        Token::Punct('(') | Token::Punct('[') => *depth += 1,
+                        Token::Punct(')') | Token::Punct(']') => *depth -= 1,
+                        _ => {}
+                    }
+                    Some(*depth == 0 && token == &Token::Punct(','))
+                })
+                .filter(|&comma| comma)
+                .count();
+        }
+        j = close + 1;
+    }
+
+    let mut visibility = None;
+    let mut modifiers = Vec::new();
+    while j < end && !is_punct(tokens.get(j), '{') && !is_punct(tokens.get(j), ';') {
+        match &tokens[j] {
+            Token::Punct('(') => j = closing(tokens, j, end),
+            Token::Ident(ident)
+                if ["public", "external", "internal", "private"].contains(&ident.as_str()) =>
+            {
+                visibility = Some(ident.clone())
+            }
+            Token::Ident(ident) if !HEADER_KEYWORDS.contains(&ident.as_str()) => {
+                modifiers.push(ident.clone())
+            }
+            _ => {}
+        }
+        j += 1;
+    }
+
+    // Declarations without a body, e.g. in interfaces, don't have any flow
+    if !is_punct(tokens.get(j), '{') {
+        return j + 1;
+    }
+    let body_end = closing(tokens, j, end);
+
+    let public = match kind {
+        Kind::Modifier => false,
+        Kind::Function => {
+            !contract.is_empty()
+                && !matches!(visibility.as_deref(), Some("internal") | Some("private"))
+        }
+        _ => true,
+    };
+
+    defs.push(Definition {
+        contract: contract.to_string(),
+        name,
+        kind,
+        params,
+        public,
+        modifiers,
+        calls: calls(&tokens[j + 1..body_end]),
+    });
+    body_end + 1
+}
+
+/// Finds the calls in a function body, calls on other variables are external and ignored
+fn calls(body: &[Token]) -> Vec<Call> {
+    let mut calls = Vec::new();
+    for i in 0..body.len() {
+        let name = match &body[i] {
+            Token::Ident(name) if is_punct(body.get(i + 1), '(') => name.clone(),
+            _ => continue,
+        };
+        let previous = if i > 0 { body.get(i - 1) } else { None };
+        if ["emit", "new", "function"]
+            .iter()
+            .any(|keyword| is_ident(previous, keyword))
+        {
+            continue;
+        }
+        if !is_punct(previous, '.') {
+            calls.push(Call::Bare(name));
+            continue;
+        }
+        match if i > 1 { body.get(i - 2) } else { None } {
+            Some(Token::Ident(target)) if target == "this" => calls.push(Call::Bare(name)),
+            Some(Token::Ident(target)) if target == "super" => calls.push(Call::Super(name)),
+            Some(Token::Ident(target)) => calls.push(Call::Qualified(target.clone(), name)),
+            _ => {}
+        }
+    }
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNABLE: &str = r#"
+pragma solidity ^0.8.0;
+
+/* A contract { function ignored() public {} } in a comment */
+contract Ownable {
+    address public owner;
+
+    modifier onlyOwner() {
+        _checkOwner();
+        _;
+    }
+
+    function _checkOwner() internal view {
+        require(msg.sender == owner, "Ownable: function notOwner() {");
+    }
+
+    function transferOwnership(address newOwner) public virtual onlyOwner {
+        owner = newOwner;
+    }
+}
+"#;
+
+    const VAULT: &str = r#"
+pragma solidity ^0.8.0;
+
+import "./Ownable.sol";
+
+interface IERC20 {
+    function transfer(address to, uint256 amount) external returns (bool);
+}
+
+library Math {
+    function min(uint256 a, uint256 b) internal pure returns (uint256) {
+        return a < b ? a : b;
+    }
+}
+
+contract Vault is Ownable {
+    event Withdrawn(address to, uint256 amount);
+    mapping(address => uint256) public balances;
+
+    constructor() Ownable() {}
+
+    receive() external payable {
+        deposit();
+    }
+
+    function deposit() public payable {
+        balances[msg.sender] += msg.value;
+    }
+
+    function withdraw(uint256 amount, IERC20 token) external onlyOwner {
+        uint256 sent = Math.min(amount, balances[msg.sender]);
+        _send(msg.sender, sent);
+        token.transfer(msg.sender, sent);
+        emit Withdrawn(msg.sender, sent);
+    }
+
+    function _send(address to, uint256 amount) private {
+        payable(to).transfer(amount);
+        this.deposit();
+    }
+
+    function transferOwnership(address newOwner) public override onlyOwner {
+        super.transferOwnership(newOwner);
+    }
+}
+"#;
+
+    const ABI: &str = r#"[
+        {"type":"constructor","inputs":[],"stateMutability":"nonpayable"},
+        {"type":"function","name":"deposit","inputs":[],"stateMutability":"payable"},
+        {"type":"function","name":"withdraw","inputs":[{"name":"amount","type":"uint256"},{"name":"token","type":"address"}],"stateMutability":"nonpayable"},
+        {"type":"function","name":"transferOwnership","inputs":[{"name":"newOwner","type":"address"}],"stateMutability":"nonpayable"},
+        {"type":"function","name":"balances","inputs":[{"name":"","type":"address"}],"stateMutability":"view"},
+        {"type":"event","name":"Withdrawn","inputs":[]},
+        {"type":"receive","stateMutability":"payable"}
+    ]"#;
+
+    fn sources() -> BTreeMap<String, String> {
+        let mut sources = BTreeMap::new();
+        sources.insert("Ownable.sol".to_string(), OWNABLE.to_string());
+        sources.insert("Vault.sol".to_string(), VAULT.to_string());
+        sources
+    }
+
+    fn edge(from: &str, to: &str) -> Edge {
+        Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_selector() {
+        assert_eq!(selector("transfer(address,uint256)"), "0xa9059cbb");
+        assert_eq!(selector("balanceOf(address)"), "0x70a08231");
+    }
+
+    #[test]
+    fn test_canonical_tuple() {
+        let item: AbiItem = serde_json::from_str(
+            r#"{"type":"function","name":"swap","constant":false,"payable":true,"inputs":[
+                {"type":"tuple[]","components":[{"type":"address"},{"type":"tuple","components":[{"type":"uint8"}]}]},
+                {"type":"bytes32"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(signature(&item), "swap((address,(uint8))[],bytes32)");
+        assert_eq!(mutability(&item), ("payable".to_string(), true));
+    }
+
+    #[test]
+    fn test_build_schema() {
+        let schema = build("Vault", ABI, &sources()).unwrap();
+
+        let withdraw = &schema.entry_points[1];
+        assert_eq!(withdraw.signature, "withdraw(uint256,address)");
+        assert_eq!(withdraw.selector, selector("withdraw(uint256,address)"));
+        assert_eq!(withdraw.mutability, "nonpayable");
+        assert_eq!(withdraw.modifiers, vec!["onlyOwner"]);
+        assert_eq!(withdraw.node.as_deref(), Some("Vault.withdraw"));
+
+        assert!(schema.entry_points[0].payable);
+        assert_eq!(schema.entry_points[3].name, "balances");
+        assert_eq!(schema.entry_points[3].node, None);
+        assert_eq!(schema.entry_points.len(), 4);
+
+        assert_eq!(
+            schema.edges,
+            vec![
+                edge("Ownable.onlyOwner", "Ownable._checkOwner"),
+                edge("Ownable.transferOwnership", "Ownable.onlyOwner"),
+                edge("Vault._send", "Vault.deposit"),
+                edge("Vault.receive", "Vault.deposit"),
+                edge("Vault.transferOwnership", "Ownable.onlyOwner"),
+                edge("Vault.transferOwnership", "Ownable.transferOwnership"),
+                edge("Vault.withdraw", "Math.min"),
+                edge("Vault.withdraw", "Ownable.onlyOwner"),
+                edge("Vault.withdraw", "Vault._send"),
+            ]
+        );
+
+        let entry_points: Vec<&str> = schema
+            .nodes
+            .iter()
+            .filter(|node| node.entry_point)
+            .map(|node| node.id.as_str())
+            .collect();
+        assert_eq!(
+            entry_points,
+            vec![
+                "Vault.constructor",
+                "Vault.deposit",
+                "Vault.receive",
+                "Vault.transferOwnership",
+                "Vault.withdraw"
+            ]
+        );
+        assert!(!schema.nodes.iter().any(|node| node.id.contains("ignored")));
+        assert!(!schema.nodes.iter().any(|node| node.id.contains("notOwner")));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let schema = build("Vault", ABI, &sources()).unwrap();
+        let dot = to_dot(&schema);
+        assert!(dot.starts_with("digraph \"Vault\" {\n"));
+        assert!(dot.contains("    \"Vault.withdraw\" [style=bold];\n"));
+        assert!(dot.contains("    \"Ownable.onlyOwner\" [shape=ellipse];\n"));
+        assert!(dot.contains("    \"Vault.withdraw\" -> \"Vault._send\";\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_write_schema() {
+        let dir = std::env::temp_dir().join(format!("merter_schema_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("contracts")).unwrap();
+        for (name, source) in sources() {
+            std::fs::write(dir.join("contracts").join(name), source).unwrap();
+        }
+        std::fs::write(dir.join("abi.json"), ABI).unwrap();
+        let metadata = Metadata {
+            address: "0x1111111111111111111111111111111111111111"
+                .parse()
+                .unwrap(),
+            name: "Vault".to_string(),
+            compiler_version: "v0.8.4+commit.c7e474f2".to_string(),
+            optimization_used: false,
+            runs: 200,
+            evm_version: "Default".to_string(),
+            implementation: None,
+        };
+        std::fs::write(
+            dir.join("metadata.json"),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .unwrap();
+
+        let schema = write_schema(&dir).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("schema.json")).unwrap())
+                .unwrap();
+        assert_eq!(json["entry_points"].as_array().unwrap().len(), 4);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("schema.dot")).unwrap(),
+            to_dot(&schema)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
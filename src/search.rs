@@ -0,0 +1,589 @@
+use super::chain::{self, Chain};
+use super::db;
+use super::issue::{Severity, SourceLocation};
+use super::settings;
+use super::units;
+
+use std::error::Error;
+use std::io::Write;
+
+use primitive_types::U256;
+use rusqlite::types::Value;
+use serde::Serialize;
+
+/// Represents the filters of a search, every filter that is set has to match
+#[derive(Debug, Default)]
+pub struct Query {
+    pub chain: Option<String>,
+    pub swc_id: Option<String>,
+    pub min_severity: Option<Severity>,
+    pub min_balance: Option<U256>,
+    pub compiler: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub address_prefix: Option<String>,
+}
+
+/// Represents an issue found in a stored contract
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub chain: String,
+    pub address: String,
+    pub value: String,
+    pub swc_id: String,
+    pub severity: Severity,
+    pub title: String,
+    pub description: String,
+    pub compiler_version: Option<String>,
+    pub found: String,
+    pub locations: Vec<SourceLocation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format {}, use table, json or csv", format)),
+        }
+    }
+}
+
+/// Searches the findings stored in the database of `chain` and prints them to stdout
+pub fn run_search(chain: &Chain, query: &Query, format: Format) {
+    let setting = settings::Settings::new(chain).unwrap_or_else(|err| {
+        println!(
+            "Couldn't load settings file.
+            \nTry running merter config --chain {} \n{}",
+            chain, err
+        );
+        std::process::exit(1);
+    });
+    let db = db::Db::from_settings(&setting).unwrap_or_else(|err| {
+        println!("Error: Couldn't open database \n{}", err);
+        std::process::exit(1);
+    });
+
+    let result = search(&db, query, &chain::all())
+        .and_then(|findings| write_findings(&mut std::io::stdout().lock(), &findings, format));
+    if let Err(e) = result {
+        println!("Error: Search failed \n{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Returns the stored issues that match the query, highest severity and value first
+pub fn search(
+    db: &db::Db,
+    query: &Query,
+    chains: &[Chain],
+) -> Result<Vec<Finding>, Box<dyn Error + Send + Sync>> {
+    let mut sql = String::from(
+        "SELECT contracts.chain, contracts.address, contracts.value_wei, issues.swc_id,
+            issues.severity, issues.title, issues.description, sources.compiler_version,
+            scan_jobs.finished_at, issues.locations
+         FROM issues
+         JOIN contracts ON contracts.id = issues.contract_id
+         JOIN scan_jobs ON scan_jobs.id = issues.scan_job_id
+         LEFT JOIN sources ON sources.contract_id = contracts.id
+         WHERE 1 = 1",
+    );
+    let mut params: Vec<Value> = Vec::new();
+    let mut filter = |clause: &str, value: Value| {
+        params.push(value);
+        sql.push_str(&clause.replace('?', &format!("?{}", params.len())));
+    };
+
+    if let Some(chain) = &query.chain {
+        filter(" AND contracts.chain = ?", Value::Text(chain.clone()));
+    }
+    if let Some(swc_id) = &query.swc_id {
+        filter(" AND issues.swc_id = ?", Value::Text(normalize_swc(swc_id)));
+    }
+    if let Some(min_balance) = query.min_balance {
+        filter(
+            " AND contracts.value_wei >= ?",
+            Value::Text(db::to_sql_u256(min_balance)),
+        );
+    }
+    if let Some(compiler) = &query.compiler {
+        // Versions match whole parts, 0.8.1 matches v0.8.1+commit.df193b15 but not v0.8.10
+        let version = compiler.trim_start_matches('v');
+        let escaped = escape_like(version);
+        filter(
+            " AND (sources.compiler_version = ?",
+            Value::Text(format!("v{}", version)),
+        );
+        filter(
+            " OR sources.compiler_version LIKE ? ESCAPE '\\'",
+            Value::Text(format!("v{}.%", escaped)),
+        );
+        filter(
+            " OR sources.compiler_version LIKE ? ESCAPE '\\'",
+            Value::Text(format!("v{}+%", escaped)),
+        );
+        filter(
+            " OR sources.compiler_version LIKE ? ESCAPE '\\')",
+            Value::Text(format!("v{}-%", escaped)),
+        );
+    }
+    if let Some(since) = query.since {
+        filter(" AND scan_jobs.finished_at >= ?", Value::Integer(since));
+    }
+    if let Some(until) = query.until {
+        filter(" AND scan_jobs.finished_at < ?", Value::Integer(until));
+    }
+    if let Some(prefix) = &query.address_prefix {
+        filter(
+            " AND contracts.address LIKE ?",
+            Value::Text(format!("{}%", prefix.to_lowercase())),
+        );
+    }
+
+    let mut statement = db.connection().prepare(&sql)?;
+    let rows = statement.query_map(rusqlite::params_from_iter(params), |row| {
+        let chain: String = row.get(0)?;
+        let value: Option<String> = row.get(2)?;
+        let severity: String = row.get(4)?;
+        let finished_at: Option<i64> = row.get(8)?;
+        let locations: String = row.get(9)?;
+        Ok(Finding {
+            value: value
+                .map(|v| {
+                    units::format_units(db::from_sql_u256(&v), native_decimals(chains, &chain))
+                })
+                .unwrap_or_default(),
+            chain,
+            address: row.get(1)?,
+            swc_id: row.get(3)?,
+            severity: Severity::parse(&severity),
+            title: row.get(5)?,
+            description: row.get(6)?,
+            compiler_version: row.get(7)?,
+            found: finished_at.map(format_date).unwrap_or_default(),
+            locations: serde_json::from_str(&locations).unwrap_or_default(),
+        })
+    })?;
+
+    let mut findings = Vec::new();
+    for finding in rows {
+        let finding = finding?;
+        if query.min_severity.is_none_or(|min| finding.severity >= min) {
+            findings.push(finding);
+        }
+    }
+
+    findings.sort_by(|a, b| {
+        b.severity.cmp(&a.severity).then_with(|| {
+            let value = |f: &Finding| {
+                units::parse_units(&f.value, native_decimals(chains, &f.chain)).unwrap_or_default()
+            };
+            value(b).cmp(&value(a))
+        })
+    });
+    Ok(findings)
+}
+
+/// Writes the findings as an aligned table, a json array or csv
+pub fn write_findings(
+    out: &mut dyn Write,
+    findings: &[Finding],
+    format: Format,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, findings)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(HEADER)?;
+            for finding in findings {
+                writer.write_record(row(finding))?;
+            }
+            writer.flush()?;
+        }
+        Format::Table => {
+            let rows: Vec<[String; 9]> = findings.iter().map(row).collect();
+            let mut widths = HEADER.map(str::len);
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                    *width = std::cmp::max(*width, cell.chars().count());
+                }
+            }
+
+            let header = HEADER.map(str::to_string);
+            for row in std::iter::once(&header).chain(rows.iter()) {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(widths.iter())
+                    .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                    .collect();
+                writeln!(out, "{}", line.join("  ").trim_end())?;
+            }
+            writeln!(out, "{} findings", findings.len())?;
+        }
+    }
+    Ok(())
+}
+
+const HEADER: [&str; 9] = [
+    "chain", "address", "value", "swc", "severity", "title", "compiler", "found", "location",
+];
+
+fn row(finding: &Finding) -> [String; 9] {
+    let location = finding
+        .locations
+        .first()
+        .map(|l| match l.line {
+            Some(line) => format!("{}:{}", l.file, line),
+            None => l.file.clone(),
+        })
+        .unwrap_or_default();
+
+    [
+        finding.chain.clone(),
+        finding.address.clone(),
+        finding.value.clone(),
+        finding.swc_id.clone(),
+        finding.severity.to_string(),
+        finding.title.clone(),
+        finding.compiler_version.clone().unwrap_or_default(),
+        finding.found.clone(),
+        location,
+    ]
+}
+
+/// Returns the decimals of the native coin of a chain, chains that are no longer configured
+/// have the default decimals
+fn native_decimals(chains: &[Chain], name: &str) -> usize {
+    chains
+        .iter()
+        .find(|chain| chain.name == name)
+        .map_or(chain::DEFAULT_DECIMALS, |chain| chain.decimals)
+}
+
+/// Escapes the wildcards of a LIKE pattern, for use with ESCAPE '\'
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Accepts 107, swc-107 and SWC-107
+fn normalize_swc(swc_id: &str) -> String {
+    let id = swc_id.trim();
+    let number = if id.len() > 4 && id.get(..4).is_some_and(|p| p.eq_ignore_ascii_case("swc-")) {
+        &id[4..]
+    } else {
+        id
+    };
+    format!("SWC-{}", number)
+}
+
+/// Checks that an address prefix only contains hex characters
+pub fn parse_address_prefix(prefix: &str) -> Result<String, String> {
+    let digits = prefix.strip_prefix("0x").unwrap_or(prefix);
+    if digits.len() > 40 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} is not an address prefix", prefix));
+    }
+    Ok(format!("0x{}", digits.to_lowercase()))
+}
+
+/// Parses a YYYY-MM-DD date into unix seconds at midnight utc
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    let invalid = || format!("{} is not a date, use YYYY-MM-DD", date);
+    let parts: Vec<&str> = date.trim().split('-').collect();
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+    let year: i64 = parts[0].parse().map_err(|_| invalid())?;
+    let month: i64 = parts[1].parse().map_err(|_| invalid())?;
+    let day: i64 = parts[2].parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Ok((era * 146097 + doe - 719468) * 86400)
+}
+
+/// Formats unix seconds as YYYY-MM-DD
+pub fn format_date(timestamp: i64) -> String {
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = timestamp.div_euclid(86400) + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explorer::Metadata;
+    use crate::issue::Issue;
+    use std::path::Path;
+
+    fn issue(swc_id: &str, severity: Severity) -> Issue {
+        Issue {
+            swc_id: swc_id.to_string(),
+            title: "Title".to_string(),
+            severity,
+            description: "Description".to_string(),
+            locations: vec![SourceLocation {
+                file: "Vault.sol".to_string(),
+                offset: 42,
+                length: 10,
+                line: Some(3),
+            }],
+        }
+    }
+
+    fn store(
+        db: &mut db::Db,
+        chain: &str,
+        address: &str,
+        ether: &str,
+        compiler: &str,
+        issues: &[Issue],
+    ) {
+        let id = db
+            .upsert_contract(chain, &address.parse().unwrap())
+            .unwrap();
+        let value = units::parse_ether(ether).unwrap();
+        db.record_balance(id, "ETH", 18, value, value, &[]).unwrap();
+        let metadata = Metadata {
+            address: address.parse().unwrap(),
+            name: "Vault".to_string(),
+            compiler_version: compiler.to_string(),
+            optimization_used: false,
+            runs: 0,
+            evm_version: "Default".to_string(),
+            implementation: None,
+        };
+        db.save_source(id, Path::new("/tmp"), &metadata).unwrap();
+        let job = db.start_scan(id, "uuid").unwrap();
+        db.finish_scan(job, issues).unwrap();
+    }
+
+    fn seeded() -> db::Db {
+        let mut db = db::Db::open("sqlite::memory:").unwrap();
+        store(
+            &mut db,
+            "eth",
+            "0xaa00000000000000000000000000000000000001",
+            "10",
+            "v0.8.4+commit.c7e474f2",
+            &[
+                issue("SWC-107", Severity::High),
+                issue("SWC-103", Severity::Low),
+            ],
+        );
+        store(
+            &mut db,
+            "bsc",
+            "0xbb00000000000000000000000000000000000002",
+            "0.5",
+            "v0.6.12+commit.27d51765",
+            &[issue("SWC-107", Severity::Medium)],
+        );
+        db
+    }
+
+    fn addresses(findings: &[Finding]) -> Vec<(&str, &str)> {
+        findings
+            .iter()
+            .map(|f| (&f.address[..4], f.swc_id.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_everything_sorted() {
+        let db = seeded();
+        let findings = search(&db, &Query::default(), &chain::builtin()).unwrap();
+        assert_eq!(
+            addresses(&findings),
+            vec![
+                ("0xaa", "SWC-107"),
+                ("0xbb", "SWC-107"),
+                ("0xaa", "SWC-103")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let db = seeded();
+        let run = |query: Query| addresses(&search(&db, &query, &chain::builtin()).unwrap()).len();
+
+        assert_eq!(
+            run(Query {
+                chain: Some("bsc".to_string()),
+                ..Query::default()
+            }),
+            1
+        );
+        assert_eq!(
+            run(Query {
+                swc_id: Some("107".to_string()),
+                ..Query::default()
+            }),
+            2
+        );
+        assert_eq!(
+            run(Query {
+                min_severity: Some(Severity::Medium),
+                ..Query::default()
+            }),
+            2
+        );
+        assert_eq!(
+            run(Query {
+                min_balance: Some(units::parse_ether("1").unwrap()),
+                ..Query::default()
+            }),
+            2
+        );
+        assert_eq!(
+            run(Query {
+                compiler: Some("0.6".to_string()),
+                ..Query::default()
+            }),
+            1
+        );
+        assert_eq!(
+            run(Query {
+                address_prefix: Some("0xBB".to_string()),
+                ..Query::default()
+            }),
+            1
+        );
+        assert_eq!(
+            run(Query {
+                since: Some(db::now() + 86400),
+                ..Query::default()
+            }),
+            0
+        );
+        assert_eq!(
+            run(Query {
+                until: Some(db::now() + 86400),
+                ..Query::default()
+            }),
+            3
+        );
+    }
+
+    #[test]
+    fn test_search_compiler_matches_whole_versions() {
+        let mut db = db::Db::open("sqlite::memory:").unwrap();
+        for (address, compiler) in &[
+            (
+                "0xcc00000000000000000000000000000000000001",
+                "v0.8.1+commit.df193b15",
+            ),
+            (
+                "0xdd00000000000000000000000000000000000002",
+                "v0.8.10+commit.fc410830",
+            ),
+        ] {
+            store(
+                &mut db,
+                "eth",
+                address,
+                "1",
+                compiler,
+                &[issue("SWC-107", Severity::High)],
+            );
+        }
+        let run = |compiler: &str| {
+            let query = Query {
+                compiler: Some(compiler.to_string()),
+                ..Query::default()
+            };
+            let findings = search(&db, &query, &chain::builtin()).unwrap();
+            findings
+                .iter()
+                .map(|f| f.address[..4].to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run("0.8.1"), vec!["0xcc"]);
+        assert_eq!(run("v0.8.10"), vec!["0xdd"]);
+        assert_eq!(run("0.8").len(), 2);
+        assert!(run("0.8.1%").is_empty());
+        assert!(run("0.8._").is_empty());
+    }
+
+    #[test]
+    fn test_write_formats() {
+        let db = seeded();
+        let findings = search(
+            &db,
+            &Query {
+                chain: Some("bsc".to_string()),
+                ..Query::default()
+            },
+            &chain::builtin(),
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        write_findings(&mut out, &findings, Format::Csv).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert!(csv.starts_with("chain,address,value,swc,severity,title,compiler,found,location\n"));
+        assert!(csv.contains("bsc,0xbb00000000000000000000000000000000000002,0.5,SWC-107,Medium,"));
+
+        let mut out = Vec::new();
+        write_findings(&mut out, &findings, Format::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["swc_id"], "SWC-107");
+        assert_eq!(json[0]["locations"][0]["line"], 3);
+
+        let mut out = Vec::new();
+        write_findings(&mut out, &findings, Format::Table).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        assert!(table.lines().next().unwrap().starts_with("chain  address"));
+        assert!(table.ends_with("1 findings\n"));
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2021-06-01").unwrap(), 1622505600);
+        assert_eq!(format_date(1622505600 + 3600), "2021-06-01");
+        assert_eq!(format_date(parse_date("2024-02-29").unwrap()), "2024-02-29");
+        assert!(parse_date("2021-13-01").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize_swc("swc-107"), "SWC-107");
+        assert_eq!(normalize_swc("107"), "SWC-107");
+        assert_eq!(normalize_swc("ü107"), "SWC-ü107");
+        assert_eq!(normalize_swc("swcü"), "SWC-swcü");
+        assert_eq!(parse_address_prefix("0xAB").unwrap(), "0xab");
+        assert!(parse_address_prefix("0xzz").is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use super::address::Address;
+use super::chain::Chain;
+
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
 use std::io::prelude::*;
@@ -11,27 +14,158 @@ pub struct Storage {
     pub file_path: String,
 }
 
-/// Represents the url to use for acces to json-rpc
-/// The latency in ms is set for each individual url
-/// Calculate latency by 1000/rate_limit_per_second
+/// Represents the rate limit of an endpoint. Up to burst requests are sent at once, after
+/// that requests_per_second. 0 requests_per_second means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct RateLimit {
+    #[serde(default)]
+    pub requests_per_second: f64,
+    #[serde(default)]
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// The free etherscan/bscscan plans allow 5 requests per second
+    fn explorer() -> Self {
+        RateLimit {
+            requests_per_second: 5.0,
+            burst: 1,
+        }
+    }
+}
+
+/// Represents a json-rpc endpoint. Requests are spread over the endpoints by weight, an
+/// endpoint with weight 2 gets twice the requests of one with weight 1.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Endpoint {
+    pub url: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Endpoint {
+    pub fn new(url: &str) -> Self {
+        Endpoint {
+            url: url.to_string(),
+            weight: default_weight(),
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+/// Represents the json-rpc endpoints, configured as a list:
+///
+/// ```toml
+/// [[jsonrpc.endpoints]]
+/// url = "https://node-1"
+/// weight = 2
+/// rate_limit = { requests_per_second = 10, burst = 5 }
+/// ```
+///
+/// The url_1 and url_2 of older config files are still read, with one request every
+/// latency ms as their rate limit.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct JsonRpc {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub url_1: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub url_2: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub latency_1: usize,
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub latency_2: usize,
+    /// Amount of requests sent in one batch request
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+}
+
+fn default_batch_size() -> usize {
+    100
+}
+
+impl Default for JsonRpc {
+    fn default() -> Self {
+        JsonRpc {
+            url_1: String::new(),
+            url_2: String::new(),
+            latency_1: 0,
+            latency_2: 0,
+            batch_size: default_batch_size(),
+            endpoints: Vec::new(),
+        }
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
 }
 
-/// Represents a BSCScan or EtherScan api key depending on the config file
+impl JsonRpc {
+    /// Returns every endpoint, the legacy urls first
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        let legacy = [(&self.url_1, self.latency_1), (&self.url_2, self.latency_2)];
+        legacy
+            .iter()
+            .filter(|(url, _)| valid_url(url))
+            .map(|(url, latency)| Endpoint {
+                rate_limit: RateLimit {
+                    requests_per_second: if *latency > 0 {
+                        1000.0 / *latency as f64
+                    } else {
+                        0.0
+                    },
+                    burst: 1,
+                },
+                ..Endpoint::new(url)
+            })
+            .chain(self.endpoints.iter().cloned())
+            .collect()
+    }
+}
+
+/// Represents the api key of the chain's block explorer, e.g. EtherScan or BscScan
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Scan {
     pub key: String,
+    #[serde(default = "RateLimit::explorer")]
+    pub rate_limit: RateLimit,
 }
 
 /// Represents a MythX api key
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MythX {
     pub key: String,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+}
+
+/// Represents a token of which the balance counts towards the value of a contract.
+/// The price is the value of one whole token in the native coin of the chain.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Token {
+    pub symbol: String,
+    pub address: Address,
+    #[serde(default)]
+    pub price: f64,
+}
+
+/// Represents the tokens checked on top of the built-in list for the chain. A token with the
+/// same symbol as a built-in token replaces it. native_usd is the price of the native coin in usd
+/// and is used to value the built-in stablecoins, they aren't counted when it's 0.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Tokens {
+    #[serde(default)]
+    pub native_usd: f64,
+    #[serde(default)]
+    pub list: Vec<Token>,
 }
 
 /// Represents the settings as in the config file
@@ -41,19 +175,51 @@ pub struct Settings {
     pub jsonrpc: JsonRpc,
     pub scan: Scan,
     pub mythx: MythX,
+    #[serde(default)]
+    pub tokens: Tokens,
+}
+
+/// Settings that can be set with merter config set and environment variables, with the type
+/// they have in the config file
+const KEYS: [(&str, Kind); 14] = [
+    ("storage.db_url", Kind::Text),
+    ("storage.file_path", Kind::Text),
+    ("jsonrpc.url_1", Kind::Url),
+    ("jsonrpc.url_2", Kind::Url),
+    ("jsonrpc.latency_1", Kind::Integer),
+    ("jsonrpc.latency_2", Kind::Integer),
+    ("jsonrpc.batch_size", Kind::Integer),
+    ("scan.key", Kind::Text),
+    ("scan.rate_limit.requests_per_second", Kind::Number),
+    ("scan.rate_limit.burst", Kind::Integer),
+    ("mythx.key", Kind::Text),
+    ("mythx.rate_limit.requests_per_second", Kind::Number),
+    ("mythx.rate_limit.burst", Kind::Integer),
+    ("tokens.native_usd", Kind::Number),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    Url,
+    Integer,
+    Number,
 }
 
 impl Settings {
-    /// Reads the settings file in the config dir and initializes it as a public struct
-    /// If there is a local config file in the executable's working directory it takes
-    /// precedence over the config file in the config dir.
+    /// Reads the settings file in the config dir and initializes it as a public struct.
+    /// Settings are read in this order, every step overrides the ones before it:
+    ///
+    /// 1. the config file in the config dir
+    /// 2. the config file in the executable's working directory
+    /// 3. `MERTER_<CHAIN>_<KEY>` environment variables, where the key is one of KEYS in
+    ///    uppercase with dots as underscores, e.g. `MERTER_ETH_JSONRPC_URL_1`
     ///
     /// # Arguments
     ///
-    /// * `chain` - A string slice that holds the current chain, will decide which config file
-    ///             to use.
+    /// * `chain` - The current chain, its config_file decides which config file to use.
     ///
-    pub fn new(chain: &str) -> Result<Self, ConfigError> {
+    pub fn new(chain: &Chain) -> Result<Self, ConfigError> {
         let mut s = Config::default();
 
         // Merge settings from config dir
@@ -76,6 +242,17 @@ impl Settings {
             }
         }
 
+        // Environment variables override both files
+        for (key, value) in
+            env_overrides(chain, |name| std::env::var(name).ok()).map_err(ConfigError::Message)?
+        {
+            match value {
+                toml::Value::Integer(n) => s.set(key, n)?,
+                toml::Value::Float(n) => s.set(key, n)?,
+                value => s.set(key, value.as_str().unwrap_or_default().to_string())?,
+            };
+        }
+
         // You can deserialize (and thus freeze) the entire configuration as
         s.try_into()
     }
@@ -87,132 +264,562 @@ impl Settings {
                 db_url: "".to_string(),
                 file_path: "".to_string(),
             },
-            jsonrpc: JsonRpc {
-                url_1: "".to_string(),
-                url_2: "".to_string(),
-                latency_1: 0,
-                latency_2: 0,
-            },
+            jsonrpc: JsonRpc::default(),
             scan: Scan {
                 key: "".to_string(),
+                rate_limit: RateLimit::explorer(),
             },
             mythx: MythX {
                 key: "".to_string(),
+                rate_limit: RateLimit::default(),
             },
+            tokens: Tokens::default(),
         }
     }
 }
 
-///Gets the config path, asks for the settings and write to config file. If the config dir
-///doesn't exist it will create the conf file in the working dir of the executable.
-pub fn run_setup(chain: &str) {
-    let config_path = return_config_path(chain)
-        .or_else(|err| {
-            println!(
-                "Error: Couldn't find config directory, falling back to working directory \n{}",
-                err
-            );
-            return_local_path(chain)
-        })
-        .unwrap_or_else(|err| {
-            println!("Error: Couldn't find working directory, exiting \n{}", err);
+/// Asks for the settings and writes them to the config file in the config dir, each current
+/// value is offered as default. The current values are read like Settings::new reads them, so
+/// they're the ones merter runs with. Values set in the working dir file or the environment
+/// are only written when they're changed. With a field only that setting is asked. If the
+/// config dir doesn't exist it will create the conf file in the working dir of the executable.
+pub fn run_setup(chain: &Chain, field: Option<&str>) {
+    let config_path = setup_path(chain);
+    let table = current_table(chain).unwrap_or_else(|err| {
+        println!("Error: Couldn't read settings. \n {}", err);
+        std::process::exit(1);
+    });
+
+    if let Some(key) = field {
+        let value = ask_field(&mut Terminal, &table, key).unwrap_or_else(|err| {
+            println!("Error: {}", err);
             std::process::exit(1);
         });
+        write_updates(chain, vec![(key, value)], &[]);
+        return;
+    }
+
+    let (updates, removed) = wizard_updates(&mut Terminal, chain, &table, |key| {
+        overridden(chain, &config_path, key)
+    })
+    .unwrap_or_else(|err| {
+        println!("Error: Couldn't read settings. \n {}", err);
+        std::process::exit(1);
+    });
+    write_updates(chain, updates, &removed);
+}
+
+/// Settings asked by the wizard besides the json-rpc endpoints
+const WIZARD_KEYS: [&str; 4] = [
+    "scan.key",
+    "mythx.key",
+    "storage.db_url",
+    "storage.file_path",
+];
 
-    let setup_struct = ask_for_settings(chain, &config_path);
+/// Legacy json-rpc settings, replaced by jsonrpc.endpoints when the wizard writes those
+const LEGACY_KEYS: [&str; 4] = [
+    "jsonrpc.url_1",
+    "jsonrpc.url_2",
+    "jsonrpc.latency_1",
+    "jsonrpc.latency_2",
+];
 
-    let toml = toml::to_string(&setup_struct).unwrap_or_else(|err| {
-        println!("Error: Couldn't parse settings as toml. \n {}", err);
+/// Keys to write to the config file and keys to remove from it
+type Changes = (Vec<(&'static str, toml::Value)>, Vec<&'static str>);
+
+/// Runs the wizard on the current settings in table and returns the keys to write to the
+/// config file and the keys to remove from it. overridden returns where a key is set outside
+/// the config file. A setting set outside the config file is only written when the answer
+/// differs from it, and urls set outside it are left out of the endpoints.
+fn wizard_updates(
+    prompt: &mut impl Prompt,
+    chain: &Chain,
+    table: &toml::value::Table,
+    overridden: impl Fn(&str) -> Option<String>,
+) -> Result<Changes, Box<dyn std::error::Error>> {
+    let current: Settings = toml::Value::Table(table.clone()).try_into()?;
+    let current_endpoints = current.jsonrpc.endpoints();
+    let answers = ask_for_settings(prompt, chain, current);
+    let answered = match toml::Value::try_from(&answers)? {
+        toml::Value::Table(table) => table,
+        _ => toml::value::Table::new(),
+    };
+
+    let mut updates = Vec::new();
+    for key in WIZARD_KEYS.iter() {
+        let value = lookup(&answered, key);
+        let changed = value != lookup(table, key);
+        if let Some(value) = value.filter(|_| changed || overridden(key).is_none()) {
+            updates.push((*key, value.clone()));
+        }
+    }
+
+    let mut removed = Vec::new();
+    if answers.jsonrpc.endpoints != current_endpoints {
+        let mut outside = Vec::new();
+        for key in LEGACY_KEYS[..2].iter() {
+            if let (Some(source), Some(url)) = (overridden(key), get_key(table, key)) {
+                if !answers.jsonrpc.endpoints.iter().any(|e| e.url == url) {
+                    println!(
+                        "Warning: {} is still used, it's set in {} as {}",
+                        url, source, key
+                    );
+                }
+                outside.push(url);
+            }
+        }
+        let endpoints: Vec<&Endpoint> = answers
+            .jsonrpc
+            .endpoints
+            .iter()
+            .filter(|e| !outside.contains(&e.url))
+            .collect();
+        updates.push(("jsonrpc.endpoints", toml::Value::try_from(endpoints)?));
+        removed.extend_from_slice(&LEGACY_KEYS);
+    }
+    Ok((updates, removed))
+}
+
+/// Sets a single setting in the config file, the rest of the file is kept
+pub fn run_set(chain: &Chain, key: &str, value: &str) {
+    let value = parse_value(key, value).unwrap_or_else(|err| {
+        println!("Error: {}", err);
         std::process::exit(1);
     });
+    write_updates(chain, vec![(key, value)], &[]);
+}
 
-    create_conf_file(&config_path, toml).unwrap_or_else(|err| {
-        println!("Error: Couldn't write settings file. \n {}", err);
+/// Writes the settings set in MERTER_<CHAIN>_<KEY> environment variables to the config file,
+/// so merter can be set up without asking anything
+pub fn run_from_env(chain: &Chain) {
+    let updates = env_overrides(chain, |name| std::env::var(name).ok()).unwrap_or_else(|err| {
+        println!("Error: {}", err);
         std::process::exit(1);
     });
+    if updates.is_empty() {
+        println!(
+            "Error: no settings found in the environment, set e.g. {}",
+            env_var(chain, KEYS[2].0)
+        );
+        std::process::exit(1);
+    }
+    write_updates(chain, updates, &[]);
+}
 
+fn write_updates(chain: &Chain, updates: Vec<(&str, toml::Value)>, removed: &[&str]) {
+    let config_path = setup_path(chain);
+    update_file(&config_path, &updates, removed).unwrap_or_else(|err| {
+        println!("Error: Couldn't write settings file. \n {}", err);
+        std::process::exit(1);
+    });
+    for (key, _) in &updates {
+        println!("{} set", key);
+        if let Some(source) = overridden(chain, &config_path, key) {
+            println!(
+                "Warning: {} is also set in {}, which takes precedence",
+                key, source
+            );
+        }
+    }
     println!("{} written!", config_path.display());
 }
 
-/// Asks the user for all the configurations in settings::Setting.
-fn ask_for_settings(chain: &str, config_path: &Path) -> Settings {
-    println!(
-        "Are you shure you want to overwrite settings file: {} (y/n)",
-        config_path.display()
-    );
-    let answ: String = text_io::read!("{}\n");
+/// Returns what overrides a key written to the config file at config_path, the config file in
+/// the working dir or an environment variable
+fn overridden(chain: &Chain, config_path: &Path, key: &str) -> Option<String> {
+    let name = env_var(chain, key);
+    if std::env::var(&name).is_ok() {
+        return Some(name);
+    }
+    let local_path = return_local_path(chain).ok()?;
+    if local_path == config_path {
+        return None;
+    }
+    let local = read_table(&local_path).ok()?;
+    lookup(&local, key).map(|_| local_path.display().to_string())
+}
 
-    if answ.eq_ignore_ascii_case("y") || answ.eq_ignore_ascii_case("yes") {
-    } else {
-        std::process::exit(1);
+/// Sets keys in a config file and removes the removed ones, creating the file when it doesn't
+/// exist
+fn update_file(
+    config_path: &Path,
+    updates: &[(&str, toml::Value)],
+    removed: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut table = read_table(config_path)?;
+    for key in removed {
+        remove_key(&mut table, key);
     }
+    for (key, value) in updates {
+        set_key(&mut table, key, value.clone());
+    }
+    create_conf_file(config_path, toml::to_string(&table)?)
+}
 
-    let mut setup_struct = Settings::default();
+/// Returns the table in a config file, an empty table when there is no file
+fn read_table(config_path: &Path) -> Result<toml::value::Table, Box<dyn std::error::Error>> {
+    match std::fs::read_to_string(config_path) {
+        Ok(content) => Ok(toml::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::value::Table::new()),
+        Err(e) => Err(e.into()),
+    }
+}
 
-    while !valid_url(&setup_struct.jsonrpc.url_1) {
-        println!("Enter JSON-RPC 1 api url:");
-        setup_struct.jsonrpc.url_1 = text_io::read!("{}\n");
-        if !valid_url(&setup_struct.jsonrpc.url_1) {
-            println!("url invalid, use http(s)://");
+/// Returns the settings merter runs with, in the same order as Settings::new
+fn current_table(chain: &Chain) -> Result<toml::value::Table, Box<dyn std::error::Error>> {
+    let paths: Vec<PathBuf> = vec![return_config_path(chain), return_local_path(chain)]
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect();
+    let overrides = env_overrides(chain, |name| std::env::var(name).ok())?;
+    load_table(&paths, overrides)
+}
+
+/// Returns the config files on top of the default settings and the overrides on top of those,
+/// so files that only have some of the settings still load. Later files take precedence.
+fn load_table(
+    paths: &[PathBuf],
+    overrides: Vec<(&str, toml::Value)>,
+) -> Result<toml::value::Table, Box<dyn std::error::Error>> {
+    let mut table = match toml::Value::try_from(Settings::default())? {
+        toml::Value::Table(table) => table,
+        _ => toml::value::Table::new(),
+    };
+    for path in paths {
+        merge_table(&mut table, read_table(path)?);
+    }
+    for (key, value) in overrides {
+        set_key(&mut table, key, value);
+    }
+    Ok(table)
+}
+
+fn merge_table(table: &mut toml::value::Table, other: toml::value::Table) {
+    for (key, value) in other {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(other)) => {
+                merge_table(table, other)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
         }
     }
+}
 
-    while !valid_url(&setup_struct.jsonrpc.url_2) {
-        println!("Enter JSON-RPC 2 api url (optional press s to skip):");
-        setup_struct.jsonrpc.url_2 = text_io::read!("{}\n");
-        if setup_struct.jsonrpc.url_2.eq("s") {
-            break;
+/// Returns the value of a dotted key in a toml table
+fn lookup<'a>(table: &'a toml::value::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.get(part)?;
+    }
+    Some(value)
+}
+
+/// Returns the value of a dotted key in a toml table as it's shown to the user
+fn get_key(table: &toml::value::Table, key: &str) -> Option<String> {
+    match lookup(table, key)? {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(n) => Some(n.to_string()),
+        toml::Value::Float(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Sets a dotted key in a toml table, creating the tables on the way
+fn set_key(table: &mut toml::value::Table, key: &str, value: toml::Value) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap_or_default();
+    let mut table = table;
+    for part in parts {
+        let entry = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::value::Table::new());
         }
-        if !valid_url(&setup_struct.jsonrpc.url_2) {
-            println!("url invalid, use http(s)://");
+        table = entry.as_table_mut().unwrap();
+    }
+    table.insert(last.to_string(), value);
+}
+
+/// Removes a dotted key from a toml table, if it's there
+fn remove_key(table: &mut toml::value::Table, key: &str) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap_or_default();
+    let mut table = table;
+    for part in parts {
+        match table.get_mut(part).and_then(toml::Value::as_table_mut) {
+            Some(inner) => table = inner,
+            None => return,
         }
     }
+    table.remove(last);
+}
 
-    println!("Enter JSON-RPC 1's latency in ms");
-    setup_struct.jsonrpc.latency_1 = text_io::read!("{}\n");
+/// Parses a value for a key to the type it has in the config file
+fn parse_value(key: &str, value: &str) -> Result<toml::Value, String> {
+    match kind(key)? {
+        Kind::Text => Ok(toml::Value::String(value.to_string())),
+        Kind::Url if valid_url(value) => Ok(toml::Value::String(value.to_string())),
+        Kind::Url => Err(format!("{} must be a http(s):// url", key)),
+        Kind::Integer => value
+            .parse::<u32>()
+            .map(|n| toml::Value::Integer(i64::from(n)))
+            .map_err(|_| format!("{} must be a whole number, not {:?}", key, value)),
+        Kind::Number => value
+            .parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite() && *n >= 0.0)
+            .map(toml::Value::Float)
+            .ok_or_else(|| format!("{} must be a number, not {:?}", key, value)),
+    }
+}
+
+/// Returns the type of a key in the config file
+fn kind(key: &str) -> Result<Kind, String> {
+    KEYS.iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| {
+            let keys: Vec<&str> = KEYS.iter().map(|(k, _)| *k).collect();
+            format!(
+                "unknown setting {}, valid settings are {}",
+                key,
+                keys.join(", ")
+            )
+        })
+}
 
-    if setup_struct.jsonrpc.url_2.chars().count() > 2 {
-        println!("Enter JSON-RPC 2's latency in ms");
-        setup_struct.jsonrpc.latency_2 = text_io::read!("{}\n");
+/// Returns the environment variable that overrides a key on a chain
+fn env_var(chain: &Chain, key: &str) -> String {
+    format!("MERTER_{}_{}", chain.name, key)
+        .to_uppercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// Returns the settings set in environment variables for a chain, lookup returns the value of
+/// an environment variable
+fn env_overrides(
+    chain: &Chain,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(&'static str, toml::Value)>, String> {
+    let mut overrides = Vec::new();
+    for (key, _) in KEYS.iter() {
+        let name = env_var(chain, key);
+        if let Some(value) = lookup(&name) {
+            let value = parse_value(key, &value).map_err(|e| format!("{}: {}", name, e))?;
+            overrides.push((*key, value));
+        }
     }
+    Ok(overrides)
+}
+
+/// Returns where merter config writes the config file, the config dir or the working dir
+/// when there is no config dir
+fn setup_path(chain: &Chain) -> PathBuf {
+    return_config_path(chain)
+        .or_else(|err| {
+            println!(
+                "Error: Couldn't find config directory, falling back to working directory \n{}",
+                err
+            );
+            return_local_path(chain)
+        })
+        .unwrap_or_else(|err| {
+            println!("Error: Couldn't find working directory, exiting \n{}", err);
+            std::process::exit(1);
+        })
+}
 
-    if chain == "eth" {
-        println!("Enter EtherScan API key:");
+/// Source of the answers to the questions of merter config
+trait Prompt {
+    /// Asks a question and returns the answer, without surrounding whitespace
+    fn ask(&mut self, question: &str) -> String;
+}
+
+/// Asks the questions on the terminal
+struct Terminal;
+
+impl Prompt for Terminal {
+    fn ask(&mut self, question: &str) -> String {
+        println!("{}", question);
+        let mut answer = String::new();
+        match std::io::stdin().read_line(&mut answer) {
+            Ok(n) if n > 0 => answer.trim().to_string(),
+            _ => {
+                println!("Error: no input, exiting");
+                std::process::exit(1);
+            }
+        }
     }
-    if chain == "bsc" {
-        println!("Enter BscScan API key:");
+}
+
+/// Asks until the answer parses, the current value is shown between brackets and an empty
+/// answer keeps it.
+fn ask_value<T>(
+    prompt: &mut impl Prompt,
+    question: &str,
+    current: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> T {
+    let question = if current.is_empty() {
+        question.to_string()
+    } else {
+        format!("{} [{}]", question, current)
+    };
+    loop {
+        let answer = prompt.ask(&question);
+        let answer = if answer.is_empty() { current } else { &answer };
+        match parse(answer) {
+            Ok(value) => return value,
+            Err(err) => println!("{}", err),
+        }
     }
-    setup_struct.scan.key = text_io::read!("{}\n");
+}
 
-    println!("Enter MythX API key:");
-    setup_struct.mythx.key = text_io::read!("{}\n");
+/// Asks for a single setting of KEYS, table holds the current settings
+fn ask_field(
+    prompt: &mut impl Prompt,
+    table: &toml::value::Table,
+    key: &str,
+) -> Result<toml::Value, String> {
+    kind(key)?;
+    let current = get_key(table, key).unwrap_or_default();
+    Ok(ask_value(
+        prompt,
+        &format!("Enter {}:", key),
+        &current,
+        |value| parse_value(key, value),
+    ))
+}
 
-    while !valid_url(&setup_struct.jsonrpc.url_1) {
-        println!("Enter db url for :");
-        setup_struct.storage.db_url = text_io::read!("{}\n");
+fn ask_requests_per_second(prompt: &mut impl Prompt, url: &str, current: f64) -> f64 {
+    ask_value(
+        prompt,
+        &format!(
+            "Enter the maximum requests per second for {} (0 for no limit):",
+            url
+        ),
+        &current.to_string(),
+        |answer| {
+            answer
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite() && *n >= 0.0)
+                .ok_or_else(|| "Requests per second invalid, enter a number".to_string())
+        },
+    )
+}
+
+/// Asks the user for all the configurations in settings::Setting, starting from the current
+/// settings. The urls of older config files end up in the list of endpoints.
+fn ask_for_settings(prompt: &mut impl Prompt, chain: &Chain, mut setting: Settings) -> Settings {
+    let mut endpoints = Vec::new();
+    for endpoint in setting.jsonrpc.endpoints() {
+        let url = ask_value(
+            prompt,
+            "Enter JSON-RPC api url (- to remove it):",
+            &endpoint.url,
+            |url| match url {
+                "-" => Ok(None),
+                url if valid_url(url) => Ok(Some(url.to_string())),
+                _ => Err("url invalid, use http(s)://".to_string()),
+            },
+        );
+        if let Some(url) = url {
+            let requests_per_second =
+                ask_requests_per_second(prompt, &url, endpoint.rate_limit.requests_per_second);
+            endpoints.push(Endpoint {
+                url,
+                rate_limit: RateLimit {
+                    requests_per_second,
+                    ..endpoint.rate_limit
+                },
+                ..endpoint
+            });
+        }
     }
 
-    while !valid_path(&setup_struct.storage.file_path) {
-        println!("Enter folder where downloaded contracts will be stored:");
-        setup_struct.storage.file_path = text_io::read!("{}\n");
-        println!("Path invalid, enter a path with write acces");
+    loop {
+        let url = if endpoints.is_empty() {
+            prompt.ask("Enter JSON-RPC api url:")
+        } else {
+            prompt.ask("Enter another JSON-RPC api url (optional press enter to skip):")
+        };
+        if url.is_empty() && !endpoints.is_empty() {
+            break;
+        }
+        if !valid_url(&url) {
+            println!("url invalid, use http(s)://");
+            continue;
+        }
+
+        let requests_per_second = ask_requests_per_second(prompt, &url, 0.0);
+        endpoints.push(Endpoint {
+            rate_limit: RateLimit {
+                requests_per_second,
+                burst: 1,
+            },
+            ..Endpoint::new(&url)
+        });
     }
-    setup_struct
+    setting.jsonrpc = JsonRpc {
+        endpoints,
+        batch_size: setting.jsonrpc.batch_size,
+        ..JsonRpc::default()
+    };
+
+    setting.scan.key = ask_value(
+        prompt,
+        &format!("Enter API key for {}:", chain.explorer_api),
+        &setting.scan.key,
+        |key| Ok(key.to_string()),
+    );
+
+    setting.mythx.key = ask_value(
+        prompt,
+        "Enter MythX API key (optional press enter to skip):",
+        &setting.mythx.key,
+        |key| Ok(key.to_string()),
+    );
+
+    setting.storage.db_url = ask_value(
+        prompt,
+        "Enter db url, e.g. sqlite:///home/user/merter.db (optional press enter to use merter.db in the contracts folder):",
+        &setting.storage.db_url,
+        |url| {
+            if valid_db_url(url) {
+                Ok(url.to_string())
+            } else {
+                Err("db url invalid, use sqlite:// or a path".to_string())
+            }
+        },
+    );
+
+    setting.storage.file_path = ask_value(
+        prompt,
+        "Enter folder where downloaded contracts will be stored:",
+        &setting.storage.file_path,
+        |path| {
+            if valid_path(path) {
+                Ok(path.to_string())
+            } else {
+                Err("Path invalid, enter a path with write acces".to_string())
+            }
+        },
+    );
+    setting
 }
 
 /// Returns the location of the config file in the dirs::config_dir, for the selected chain.
-fn return_config_path(chain: &str) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+fn return_config_path(chain: &Chain) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
     match dirs::config_dir() {
         Some(mut v) => {
             v.push("merter");
-
-            if chain == "eth" {
-                v.push(".ethconf");
-            }
-            if chain == "bsc" {
-                v.push(".bscconf")
-            }
-            v.set_extension("toml");
+            v.push(&chain.config_file);
             Ok(v)
         }
         None => Err("Config dir not found".into()),
@@ -220,18 +827,11 @@ fn return_config_path(chain: &str) -> Result<std::path::PathBuf, Box<dyn std::er
 }
 
 /// Returns the location of the config file in the current working directory of the executable.
-fn return_local_path(chain: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+fn return_local_path(chain: &Chain) -> Result<PathBuf, Box<dyn std::error::Error>> {
     match std::env::current_exe() {
         Ok(mut exe_path) => {
             exe_path.pop();
-            if chain == "eth" {
-                exe_path.push(".ethconf");
-            }
-            if chain == "bsc" {
-                exe_path.push(".bscconf");
-            }
-            exe_path.set_extension("toml");
-
+            exe_path.push(&chain.config_file);
             Ok(exe_path)
         }
         Err(e) => Err(e.into()),
@@ -256,6 +856,14 @@ fn valid_url(url: &str) -> bool {
     url.starts_with("https://") || url.starts_with("http://")
 }
 
+/// Returns true for the urls Db::open accepts, empty means the default database
+fn valid_db_url(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) if scheme.len() > 1 => scheme == "sqlite",
+        _ => true,
+    }
+}
+
 fn valid_path(path: &str) -> bool {
     let path_p = Path::new(path);
 
@@ -264,3 +872,345 @@ fn valid_path(path: &str) -> bool {
         Err(e) => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        let jsonrpc: JsonRpc = toml::from_str(
+            r#"
+            url_1 = "https://legacy"
+            url_2 = "s"
+            latency_1 = 250
+
+            [[endpoints]]
+            url = "https://node"
+            weight = 3
+            rate_limit = { requests_per_second = 10, burst = 5 }
+            "#,
+        )
+        .unwrap();
+
+        let endpoints = jsonrpc.endpoints();
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].url, "https://legacy");
+        assert_eq!(endpoints[0].weight, 1);
+        assert_eq!(endpoints[0].rate_limit.requests_per_second, 4.0);
+        assert_eq!(endpoints[1].weight, 3);
+        assert_eq!(endpoints[1].rate_limit.burst, 5);
+
+        let toml = toml::to_string(&JsonRpc {
+            endpoints: vec![Endpoint::new("https://node")],
+            ..JsonRpc::default()
+        })
+        .unwrap();
+        assert!(!toml.contains("url_1"));
+        let parsed: JsonRpc = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.endpoints(), vec![Endpoint::new("https://node")]);
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(
+            parse_value("jsonrpc.batch_size", "50"),
+            Ok(toml::Value::Integer(50))
+        );
+        assert_eq!(
+            parse_value("tokens.native_usd", "2000.5"),
+            Ok(toml::Value::Float(2000.5))
+        );
+        assert!(parse_value("jsonrpc.batch_size", "-1").is_err());
+        assert!(parse_value("jsonrpc.url_1", "localhost:8545").is_err());
+        assert!(parse_value("scan.secret", "x")
+            .unwrap_err()
+            .contains("valid settings are storage.db_url"));
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let chain = &crate::chain::builtin()[0];
+        assert_eq!(env_var(chain, "jsonrpc.url_1"), "MERTER_ETH_JSONRPC_URL_1");
+
+        let overrides = env_overrides(chain, |name| match name {
+            "MERTER_ETH_JSONRPC_URL_1" => Some("https://node".to_string()),
+            "MERTER_ETH_SCAN_RATE_LIMIT_BURST" => Some("3".to_string()),
+            "MERTER_BSC_MYTHX_KEY" => Some("other chain".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(
+            overrides,
+            vec![
+                (
+                    "jsonrpc.url_1",
+                    toml::Value::String("https://node".to_string())
+                ),
+                ("scan.rate_limit.burst", toml::Value::Integer(3)),
+            ]
+        );
+
+        let err = env_overrides(chain, |name| {
+            (name == "MERTER_ETH_JSONRPC_BATCH_SIZE").then(|| "lots".to_string())
+        })
+        .unwrap_err();
+        assert!(err.starts_with("MERTER_ETH_JSONRPC_BATCH_SIZE: "));
+    }
+
+    #[test]
+    fn test_update_file_keeps_other_settings() {
+        let path = std::env::temp_dir().join(format!("merter_set_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[scan]\nkey = \"old\"\n\n[jsonrpc]\nurl_1 = \"https://legacy\"\n\n[[jsonrpc.endpoints]]\nurl = \"https://node\"\n",
+        )
+        .unwrap();
+
+        update_file(
+            &path,
+            &[
+                ("scan.key", toml::Value::String("new".to_string())),
+                ("scan.rate_limit.burst", toml::Value::Integer(2)),
+                ("mythx.key", toml::Value::String("key".to_string())),
+            ],
+            &["jsonrpc.url_1"],
+        )
+        .unwrap();
+        let table: toml::Value = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(table["scan"]["key"].as_str(), Some("new"));
+        assert_eq!(table["scan"]["rate_limit"]["burst"].as_integer(), Some(2));
+        assert_eq!(table["mythx"]["key"].as_str(), Some("key"));
+        assert_eq!(
+            table["jsonrpc"]["endpoints"][0]["url"].as_str(),
+            Some("https://node")
+        );
+        assert!(table["jsonrpc"].get("url_1").is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Answers the questions of the wizard in order
+    struct Script(std::collections::VecDeque<String>);
+
+    impl Script {
+        fn new(answers: &[&str]) -> Self {
+            Script(answers.iter().map(|a| a.to_string()).collect())
+        }
+    }
+
+    impl Prompt for Script {
+        fn ask(&mut self, question: &str) -> String {
+            self.0
+                .pop_front()
+                .unwrap_or_else(|| panic!("no answer left for {:?}", question))
+        }
+    }
+
+    fn temp_dir() -> String {
+        std::env::temp_dir().to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_wizard_keeps_current_settings() {
+        let chain = &crate::chain::builtin()[0];
+        let mut current: Settings = toml::from_str(&format!(
+            r#"
+            [storage]
+            db_url = "sqlite://merter.db"
+            file_path = "{}"
+
+            [jsonrpc]
+            url_1 = "https://legacy"
+            latency_1 = 250
+            batch_size = 20
+
+            [[jsonrpc.endpoints]]
+            url = "https://node"
+            weight = 3
+
+            [scan]
+            key = "SCAN"
+
+            [mythx]
+            key = "MYTHX"
+            "#,
+            temp_dir()
+        ))
+        .unwrap();
+        current.tokens.native_usd = 2000.0;
+
+        // Keep the legacy url, remove the endpoint and press enter on everything else
+        let mut script = Script::new(&["", "", "-", "", "", "", "", ""]);
+        let setting = ask_for_settings(&mut script, chain, current);
+        assert!(script.0.is_empty());
+
+        assert_eq!(setting.jsonrpc.url_1, "");
+        assert_eq!(setting.jsonrpc.latency_1, 0);
+        assert_eq!(setting.jsonrpc.batch_size, 20);
+        assert_eq!(setting.jsonrpc.endpoints.len(), 1);
+        assert_eq!(setting.jsonrpc.endpoints[0].url, "https://legacy");
+        assert_eq!(
+            setting.jsonrpc.endpoints[0].rate_limit,
+            RateLimit {
+                requests_per_second: 4.0,
+                burst: 1
+            }
+        );
+        assert_eq!(setting.scan.key, "SCAN");
+        assert_eq!(setting.mythx.key, "MYTHX");
+        assert_eq!(setting.storage.db_url, "sqlite://merter.db");
+        assert_eq!(setting.storage.file_path, temp_dir());
+        assert_eq!(setting.tokens.native_usd, 2000.0);
+    }
+
+    #[test]
+    fn test_wizard_validates_answers() {
+        let chain = &crate::chain::builtin()[0];
+        let folder = temp_dir();
+        let answers = [
+            "localhost:8545",
+            "https://node",
+            "fast",
+            "10",
+            "",
+            "SCAN",
+            "",
+            "postgres://localhost/merter",
+            "",
+            "/does/not/exist",
+            "",
+            &folder,
+        ];
+        let mut script = Script::new(&answers);
+        let setting = ask_for_settings(&mut script, chain, Settings::default());
+        assert!(script.0.is_empty());
+
+        assert_eq!(
+            setting.jsonrpc.endpoints(),
+            vec![Endpoint {
+                rate_limit: RateLimit {
+                    requests_per_second: 10.0,
+                    burst: 1
+                },
+                ..Endpoint::new("https://node")
+            }]
+        );
+        assert_eq!(setting.scan.key, "SCAN");
+        assert_eq!(setting.mythx.key, "");
+        assert_eq!(setting.storage.db_url, "");
+        assert_eq!(setting.storage.file_path, folder);
+    }
+
+    #[test]
+    fn test_ask_field() {
+        let dir = std::env::temp_dir();
+        let config = dir.join(format!("merter_field_{}.toml", std::process::id()));
+        let local = dir.join(format!("merter_field_local_{}.toml", std::process::id()));
+        std::fs::write(
+            &config,
+            "[jsonrpc]\nbatch_size = 20\n[scan]\nkey = \"CONFIG\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &local,
+            "[jsonrpc]\nbatch_size = 50\n[mythx]\nkey = \"LOCAL\"\n",
+        )
+        .unwrap();
+        // Like Settings::new, the working dir file and the environment win
+        let table = load_table(
+            &[
+                config.clone(),
+                local.clone(),
+                dir.join("merter_missing.toml"),
+            ],
+            vec![("mythx.key", toml::Value::String("ENV".to_string()))],
+        )
+        .unwrap();
+        std::fs::remove_file(&config).unwrap();
+        std::fs::remove_file(&local).unwrap();
+        assert_eq!(get_key(&table, "scan.key").as_deref(), Some("CONFIG"));
+        assert_eq!(get_key(&table, "mythx.key").as_deref(), Some("ENV"));
+
+        let ask = |answers: &[&str], key: &str| ask_field(&mut Script::new(answers), &table, key);
+        assert_eq!(
+            ask(&["many", "200"], "jsonrpc.batch_size"),
+            Ok(toml::Value::Integer(200))
+        );
+        assert_eq!(
+            ask(&[""], "jsonrpc.batch_size"),
+            Ok(toml::Value::Integer(50))
+        );
+        // Settings missing from the file default to those of Settings::default
+        assert_eq!(
+            ask(&[""], "scan.rate_limit.requests_per_second"),
+            Ok(toml::Value::Float(5.0))
+        );
+        assert!(ask(&[], "scan.secret").is_err());
+
+        let setting: Settings = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(setting.jsonrpc.batch_size, 50);
+    }
+
+    #[test]
+    fn test_wizard_writes_no_env_overrides() {
+        let chain = &crate::chain::builtin()[0];
+        let path = std::env::temp_dir().join(format!("merter_wizard_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            format!(
+                "[storage]\ndb_url = \"\"\nfile_path = \"{}\"\n\n[scan]\nkey = \"SCAN\"\n\n[[jsonrpc.endpoints]]\nurl = \"https://node\"\n",
+                temp_dir()
+            ),
+        )
+        .unwrap();
+        let table = load_table(
+            std::slice::from_ref(&path),
+            vec![
+                (
+                    "jsonrpc.url_1",
+                    toml::Value::String("https://env".to_string()),
+                ),
+                ("mythx.key", toml::Value::String("ENV".to_string())),
+            ],
+        )
+        .unwrap();
+        let overridden = |key: &str| {
+            ["jsonrpc.url_1", "mythx.key"]
+                .contains(&key)
+                .then(|| env_var(chain, key))
+        };
+        let wizard = |answers: &[&str]| {
+            let mut script = Script::new(answers);
+            let (updates, removed) =
+                wizard_updates(&mut script, chain, &table, overridden).unwrap();
+            assert!(script.0.is_empty());
+            update_file(&path, &updates, &removed).unwrap();
+            let file: toml::Value =
+                toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            file
+        };
+
+        // Pressing enter on everything keeps the file as it was
+        let file = wizard(&["", "", "", "", "", "", "", "", ""]);
+        assert!(file.get("mythx").is_none());
+        assert!(file["jsonrpc"].get("url_1").is_none());
+        assert_eq!(file["jsonrpc"]["endpoints"].as_array().unwrap().len(), 1);
+        assert_eq!(file["scan"]["key"].as_str(), Some("SCAN"));
+
+        // Adding an endpoint writes the endpoints of the file, not the one in the environment
+        let file = wizard(&["", "", "", "", "https://new", "", "", "", "", "", ""]);
+        let urls: Vec<&str> = file["jsonrpc"]["endpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["url"].as_str().unwrap())
+            .collect();
+        assert_eq!(urls, vec!["https://node", "https://new"]);
+        assert!(file.get("mythx").is_none());
+
+        // A changed secret is written
+        let file = wizard(&["", "", "", "", "", "", "OTHER", "", ""]);
+        assert_eq!(file["mythx"]["key"].as_str(), Some("OTHER"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::db;
+use super::issue::{Issue, Severity};
+use super::search;
+use super::settings;
+use super::units;
+
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{params, OptionalExtension};
+
+/// Represents everything merter stored about a contract
+#[derive(Debug)]
+pub struct Report {
+    pub chain: String,
+    pub address: String,
+    pub balance: String,
+    pub value: String,
+    /// Contract that runs the code when this one is a proxy
+    pub implementation: Option<String>,
+    /// Who can upgrade the proxy, directly or through a beacon
+    pub admin: Option<String>,
+    pub beacon: Option<String>,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub balances: Vec<(String, String)>,
+    pub source: Option<Source>,
+    pub scans: Vec<Scan>,
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Debug)]
+pub struct Source {
+    pub path: String,
+    pub name: String,
+    pub compiler_version: String,
+    pub implementation: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Scan {
+    pub uuid: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub submitted_at: i64,
+}
+
+/// Prints what's stored about a contract in the database of `chain`
+pub fn run_show(chain: &Chain, address: &Address) {
+    let setting = settings::Settings::new(chain).unwrap_or_else(|err| {
+        println!(
+            "Couldn't load settings file.
+            \nTry running merter config --chain {} \n{}",
+            chain, err
+        );
+        std::process::exit(1);
+    });
+    let db = db::Db::from_settings(&setting).unwrap_or_else(|err| {
+        println!("Error: Couldn't open database \n{}", err);
+        std::process::exit(1);
+    });
+
+    match report(&db, chain, address) {
+        Ok(Some(report)) => print!("{}", report),
+        Ok(None) => {
+            println!("{} isn't stored on {}", address, chain);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("Error: Couldn't read {} \n{}", address, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Collects the stored data of a contract, None when it was never seen
+pub fn report(
+    db: &db::Db,
+    chain: &Chain,
+    address: &Address,
+) -> Result<Option<Report>, Box<dyn Error + Send + Sync>> {
+    let id = match db.contract_id(&chain.name, address)? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let conn = db.connection();
+
+    let mut report = conn.query_row(
+        "SELECT chain, address, balance_wei, value_wei, first_seen, last_seen, implementation,
+            proxy_admin, proxy_beacon
+         FROM contracts WHERE id = ?1",
+        params![id],
+        |row| {
+            let balance: Option<String> = row.get(2)?;
+            let value: Option<String> = row.get(3)?;
+            Ok(Report {
+                chain: row.get(0)?,
+                address: row.get(1)?,
+                balance: format_wei(balance, chain.decimals),
+                value: format_wei(value, chain.decimals),
+                implementation: row.get(6)?,
+                admin: row.get(7)?,
+                beacon: row.get(8)?,
+                first_seen: row.get(4)?,
+                last_seen: row.get(5)?,
+                balances: Vec::new(),
+                source: None,
+                scans: Vec::new(),
+                issues: Vec::new(),
+            })
+        },
+    )?;
+
+    // Latest amount of every asset
+    let mut statement = conn.prepare(
+        "SELECT asset, amount, decimals FROM balances
+         WHERE contract_id = ?1 AND recorded_at = (
+            SELECT MAX(recorded_at) FROM balances WHERE contract_id = ?1)
+         ORDER BY id",
+    )?;
+    report.balances = statement
+        .query_map(params![id], |row| {
+            let amount: String = row.get(1)?;
+            let decimals: i64 = row.get(2)?;
+            Ok((
+                row.get(0)?,
+                units::format_units(db::from_sql_u256(&amount), decimals as usize),
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+
+    report.source = conn
+        .query_row(
+            "SELECT path, name, compiler_version, implementation FROM sources
+             WHERE contract_id = ?1",
+            params![id],
+            |row| {
+                Ok(Source {
+                    path: row.get(0)?,
+                    name: row.get(1)?,
+                    compiler_version: row.get(2)?,
+                    implementation: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+
+    let mut statement = conn.prepare(
+        "SELECT uuid, status, error, submitted_at FROM scan_jobs
+         WHERE contract_id = ?1 ORDER BY id DESC",
+    )?;
+    report.scans = statement
+        .query_map(params![id], |row| {
+            Ok(Scan {
+                uuid: row.get(0)?,
+                status: row.get(1)?,
+                error: row.get(2)?,
+                submitted_at: row.get(3)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    // Issues of the latest finished scan
+    let mut statement = conn.prepare(
+        "SELECT swc_id, title, severity, description, locations FROM issues
+         WHERE scan_job_id = (
+            SELECT MAX(id) FROM scan_jobs WHERE contract_id = ?1 AND status = 'finished')
+         ORDER BY id",
+    )?;
+    report.issues = statement
+        .query_map(params![id], |row| {
+            let severity: String = row.get(2)?;
+            let locations: String = row.get(4)?;
+            Ok(Issue {
+                swc_id: row.get(0)?,
+                title: row.get(1)?,
+                severity: Severity::parse(&severity),
+                description: row.get(3)?,
+                locations: serde_json::from_str(&locations).unwrap_or_default(),
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    report.issues.sort_by_key(|issue| Reverse(issue.severity));
+
+    Ok(Some(report))
+}
+
+fn format_wei(amount: Option<String>, decimals: usize) -> String {
+    amount
+        .map(|a| units::format_units(db::from_sql_u256(&a), decimals))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} on {}", self.address, self.chain)?;
+        writeln!(f, "  balance:    {}", self.balance)?;
+        writeln!(f, "  value:      {}", self.value)?;
+        if let Some(implementation) = &self.implementation {
+            writeln!(f, "  proxy for:  {}", implementation)?;
+        }
+        if let Some(admin) = &self.admin {
+            writeln!(f, "  admin:      {}", admin)?;
+        }
+        if let Some(beacon) = &self.beacon {
+            writeln!(f, "  beacon:     {}", beacon)?;
+        }
+        writeln!(
+            f,
+            "  seen:       {} - {}",
+            search::format_date(self.first_seen),
+            search::format_date(self.last_seen)
+        )?;
+        for (asset, amount) in &self.balances {
+            writeln!(f, "  holds:      {} {}", amount, asset)?;
+        }
+
+        match &self.source {
+            Some(source) => {
+                writeln!(
+                    f,
+                    "  source:     {} ({})",
+                    source.name, source.compiler_version
+                )?;
+                writeln!(f, "  path:       {}", source.path)?;
+                if let Some(implementation) = &source.implementation {
+                    if self.implementation.as_ref() != Some(implementation) {
+                        writeln!(f, "  proxy for:  {}", implementation)?;
+                    }
+                }
+                let schema = Path::new(&source.path).join("schema.dot");
+                if schema.exists() {
+                    writeln!(f, "  schema:     {}", schema.display())?;
+                }
+            }
+            None => writeln!(f, "  source:     not verified")?,
+        }
+
+        for scan in &self.scans {
+            write!(
+                f,
+                "  scan:       {} {} {}",
+                search::format_date(scan.submitted_at),
+                scan.uuid,
+                scan.status
+            )?;
+            match &scan.error {
+                Some(error) => writeln!(f, " ({})", error)?,
+                None => writeln!(f)?,
+            }
+        }
+
+        writeln!(f, "{} issues", self.issues.len())?;
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explorer::Metadata;
+    use crate::issue::SourceLocation;
+    use crate::proxy::Proxy;
+    use primitive_types::U256;
+
+    const ADDRESS: &str = "0xAbCd000000000000000000000000000000000001";
+
+    #[test]
+    fn test_unknown_contract() {
+        let db = db::Db::open("sqlite::memory:").unwrap();
+        let chain = &crate::chain::builtin()[0];
+        assert!(report(&db, chain, &ADDRESS.to_lowercase().parse().unwrap())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut db = db::Db::open("sqlite::memory:").unwrap();
+        let id = db
+            .upsert_contract("eth", &ADDRESS.to_lowercase().parse().unwrap())
+            .unwrap();
+        let balance = units::parse_ether("2.5").unwrap();
+        db.record_balance(id, "ETH", 18, balance, balance, &[])
+            .unwrap();
+        let implementation: Address = "0x2222222222222222222222222222222222222222"
+            .parse()
+            .unwrap();
+        db.set_proxy(
+            id,
+            &Proxy {
+                implementation,
+                admin: Some(
+                    "0x3333333333333333333333333333333333333333"
+                        .parse()
+                        .unwrap(),
+                ),
+                beacon: None,
+            },
+        )
+        .unwrap();
+        let metadata = Metadata {
+            address: ADDRESS.to_lowercase().parse().unwrap(),
+            name: "Vault".to_string(),
+            compiler_version: "v0.8.4+commit.c7e474f2".to_string(),
+            optimization_used: true,
+            runs: 200,
+            evm_version: "Default".to_string(),
+            implementation: Some(implementation),
+        };
+        db.save_source(id, Path::new("/tmp/vault"), &metadata)
+            .unwrap();
+
+        let failed = db.start_scan(id, "uuid-1").unwrap();
+        db.fail_scan(failed, "timeout").unwrap();
+        let job = db.start_scan(id, "uuid-2").unwrap();
+        let issue = |swc_id: &str, severity| Issue {
+            swc_id: swc_id.to_string(),
+            title: "Title".to_string(),
+            severity,
+            description: "Description".to_string(),
+            locations: vec![SourceLocation {
+                file: "Vault.sol".to_string(),
+                offset: 0,
+                length: 1,
+                line: Some(7),
+            }],
+        };
+        db.finish_scan(
+            job,
+            &[
+                issue("SWC-103", Severity::Low),
+                issue("SWC-107", Severity::High),
+            ],
+        )
+        .unwrap();
+
+        let chain = &crate::chain::builtin()[0];
+        let report = report(&db, chain, &ADDRESS.to_lowercase().parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.balance, "2.5");
+        assert_eq!(
+            report.balances,
+            vec![("ETH".to_string(), "2.5".to_string())]
+        );
+        assert_eq!(report.source.as_ref().unwrap().name, "Vault");
+        assert_eq!(report.scans.len(), 2);
+        assert_eq!(report.scans[1].error.as_deref(), Some("timeout"));
+        assert_eq!(report.issues[0].swc_id, "SWC-107");
+        assert_eq!(report.issues[1].swc_id, "SWC-103");
+
+        let text = report.to_string();
+        assert!(text.starts_with(&format!("{} on eth\n", ADDRESS.to_lowercase())));
+        assert!(text.contains("  source:     Vault (v0.8.4+commit.c7e474f2)\n"));
+        // The resolved implementation is shown once
+        assert_eq!(text.matches("  proxy for:  0x2222").count(), 1);
+        assert!(text.contains("  admin:      0x3333333333333333333333333333333333333333\n"));
+        assert!(!text.contains("  beacon:"));
+        assert!(text.contains("uuid-1 failed (timeout)\n"));
+        assert!(text.contains("2 issues\n[High] SWC-107 Title\n    Vault.sol:7\n"));
+        assert_eq!(format_wei(Some(db::to_sql_u256(U256::zero())), 18), "0");
+        assert_eq!(
+            format_wei(Some(db::to_sql_u256(U256::from(150_000_000u64))), 8),
+            "1.5"
+        );
+
+        // A later scan without findings leaves no current issues
+        let rescan = db.start_scan(id, "uuid-3").unwrap();
+        db.finish_scan(rescan, &[]).unwrap();
+        let rescanned = super::report(&db, chain, &ADDRESS.to_lowercase().parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(rescanned.scans.len(), 3);
+        assert!(rescanned.issues.is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use super::address::Address;
+use super::chain::Chain;
+use super::jsonrpc;
+use super::pool::Pool;
+use super::settings::{Token, Tokens};
+use super::units;
+
+use primitive_types::U256;
+
+/// balanceOf(address)
+const BALANCE_OF: &str = "0x70a08231";
+/// decimals()
+pub const DECIMALS: &str = "0x313ce567";
+
+/// Prices are converted to integers with this many decimals before valuing a balance
+const PRICE_DECIMALS: usize = 9;
+
+/// Represents a token of which the decimals are known. native_decimals are those of the
+/// native coin of the chain, the value of the token is counted in it.
+#[derive(Debug, Clone)]
+pub struct ResolvedToken {
+    pub token: Token,
+    pub decimals: usize,
+    pub native_decimals: usize,
+}
+
+/// Represents the amount of a token held by a contract, and its value in wei
+#[derive(Debug, PartialEq)]
+pub struct TokenBalance {
+    pub symbol: String,
+    pub amount: U256,
+    pub decimals: usize,
+    pub value: U256,
+}
+
+/// Returns the built-in wrapped native coin and stablecoins for a chain, chains without a
+/// built-in list only check the tokens from the config file.
+fn default_tokens(chain: &Chain, native_usd: f64) -> Vec<Token> {
+    let stable = if native_usd > 0.0 {
+        1.0 / native_usd
+    } else {
+        0.0
+    };
+
+    let list: &[(&str, &str, f64)] = match chain.name.as_str() {
+        "eth" => &[
+            ("WETH", "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", 1.0),
+            ("USDT", "0xdac17f958d2ee523a2206206994597c13d831ec7", stable),
+            ("USDC", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", stable),
+            ("DAI", "0x6b175474e89094c44da98b954eedeac495271d0f", stable),
+        ],
+        "bsc" => &[
+            ("WBNB", "0xbb4cdb9cbd36b01bd1cbaebf2de08d9173bc095c", 1.0),
+            ("BUSD", "0xe9e7cea3dedca5984780bafc599bd69add087d56", stable),
+            ("USDT", "0x55d398326f99059ff775485246999027b3197955", stable),
+            ("USDC", "0x8ac76a51cc950d9822d68b83fe1ad97b32cd580d", stable),
+        ],
+        _ => &[],
+    };
+
+    list.iter()
+        .map(|(symbol, address, price)| Token {
+            symbol: symbol.to_string(),
+            address: address.parse().expect("built-in token address"),
+            price: *price,
+        })
+        .collect()
+}
+
+/// Returns the tokens to check for a chain, the built-in ones merged with the ones from the
+/// config file.
+pub fn for_chain(chain: &Chain, config: &Tokens) -> Vec<Token> {
+    let mut tokens = default_tokens(chain, config.native_usd);
+    for token in &config.list {
+        tokens.retain(|t| !t.symbol.eq_ignore_ascii_case(&token.symbol));
+        tokens.push(token.clone());
+    }
+    tokens
+}
+
+/// Grabs decimals() of every token, tokens that fail are skipped.
+pub async fn resolve(
+    pool: &Pool,
+    tokens: Vec<Token>,
+    native_decimals: usize,
+) -> Vec<ResolvedToken> {
+    let mut resolved = Vec::new();
+    for token in tokens {
+        let decimals = pool
+            .request(|url| {
+                let address = token.address;
+                async move { jsonrpc::eth_call(&address, DECIMALS, &url).await }
+            })
+            .await
+            .map_err(Into::into)
+            .and_then(|v| units::parse_hex_u256(&v));
+
+        match decimals {
+            Ok(d) if d <= U256::from(77) => resolved.push(ResolvedToken {
+                token,
+                decimals: d.as_usize(),
+                native_decimals,
+            }),
+            Ok(d) => println!("{} has invalid decimals {}, skipping", token.symbol, d),
+            Err(e) => println!(
+                "Couldn't grab decimals of {}, skipping. \nError: {}",
+                token.symbol, e
+            ),
+        }
+    }
+    resolved
+}
+
+/// Returns the tokens held by an address, tokens that fail or have a zero balance are left out.
+pub async fn holdings(
+    pool: &Pool,
+    address: &Address,
+    tokens: &[ResolvedToken],
+) -> Vec<TokenBalance> {
+    let data = balance_of_data(address);
+    let mut balances = Vec::new();
+
+    for resolved in tokens {
+        let amount = pool
+            .request(|url| {
+                let data = &data;
+                async move { jsonrpc::eth_call(&resolved.token.address, data, &url).await }
+            })
+            .await
+            .map_err(Into::into)
+            .and_then(|v| units::parse_hex_u256(&v));
+
+        match amount {
+            Ok(amount) if !amount.is_zero() => balances.push(TokenBalance {
+                symbol: resolved.token.symbol.clone(),
+                amount,
+                decimals: resolved.decimals,
+                value: value_in_wei(
+                    amount,
+                    resolved.decimals,
+                    resolved.token.price,
+                    resolved.native_decimals,
+                ),
+            }),
+            Ok(_) => {}
+            Err(e) => println!(
+                "Couldn't grab {} balance of {}, skipping. \nError: {}",
+                resolved.token.symbol, address, e
+            ),
+        }
+    }
+    balances
+}
+
+/// Returns the native balance plus the value of the held tokens.
+pub fn total_value(native: U256, holdings: &[TokenBalance]) -> U256 {
+    holdings
+        .iter()
+        .fold(native, |total, h| total.saturating_add(h.value))
+}
+
+/// Abi encodes balanceOf(address)
+fn balance_of_data(address: &Address) -> String {
+    format!("{}{}", BALANCE_OF, address.to_word())
+}
+
+/// Converts an amount of tokens to wei using the price of one whole token in the native coin.
+fn value_in_wei(amount: U256, decimals: usize, price: f64, native_decimals: usize) -> U256 {
+    if price <= 0.0 || !price.is_finite() {
+        return U256::zero();
+    }
+    let price = U256::from((price * 10f64.powi(PRICE_DECIMALS as i32)) as u128);
+
+    amount
+        .saturating_mul(price)
+        .saturating_mul(U256::exp10(native_decimals))
+        / U256::exp10(decimals)
+        / U256::exp10(PRICE_DECIMALS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const CONTRACT: &str = "0x2222222222222222222222222222222222222222";
+    const USDT: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+
+    #[test]
+    fn test_balance_of_data() {
+        assert_eq!(
+            balance_of_data(&CONTRACT.parse().unwrap()),
+            "0x70a082310000000000000000000000002222222222222222222222222222222222222222"
+        );
+    }
+
+    #[test]
+    fn test_value_in_wei() {
+        let one_weth = U256::exp10(18);
+        assert_eq!(value_in_wei(one_weth, 18, 1.0, 18), U256::exp10(18));
+
+        // 2000 usdt at 2000 usd per eth
+        let usdt = U256::from(2000) * U256::exp10(6);
+        assert_eq!(value_in_wei(usdt, 6, 1.0 / 2000.0, 18), U256::exp10(18));
+        // on a chain of which the native coin has 8 decimals
+        assert_eq!(value_in_wei(usdt, 6, 1.0 / 2000.0, 8), U256::exp10(8));
+
+        assert_eq!(value_in_wei(usdt, 6, 0.0, 18), U256::zero());
+    }
+
+    #[test]
+    fn test_for_chain_overrides_by_symbol() {
+        let config = Tokens {
+            native_usd: 0.0,
+            list: vec![Token {
+                symbol: "usdt".to_string(),
+                address: USDT.parse().unwrap(),
+                price: 0.0005,
+            }],
+        };
+        let tokens = for_chain(&crate::chain::builtin()[0], &config);
+        let usdt: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.symbol.eq_ignore_ascii_case("usdt"))
+            .collect();
+        assert_eq!(usdt.len(), 1);
+        assert_eq!(usdt[0].price, 0.0005);
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_holdings() {
+        let server = MockServer::start().await;
+        let respond = |result: &str| {
+            ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": result,
+            }))
+        };
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "eth_call",
+                "params": [{ "to": USDT, "data": DECIMALS }]
+            })))
+            .respond_with(respond("0x6"))
+            .mount(&server)
+            .await;
+        // 4000 usdt
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "eth_call",
+                "params": [{ "to": USDT, "data": balance_of_data(&CONTRACT.parse().unwrap()) }]
+            })))
+            .respond_with(respond(
+                "0x00000000000000000000000000000000000000000000000000000000ee6b2800",
+            ))
+            .mount(&server)
+            .await;
+
+        let tokens = vec![Token {
+            symbol: "USDT".to_string(),
+            address: USDT.parse().unwrap(),
+            price: 1.0 / 2000.0,
+        }];
+        let pool = Pool::single(&server.uri());
+        let tokens = resolve(&pool, tokens, 18).await;
+        assert_eq!(tokens[0].decimals, 6);
+
+        let held = holdings(&pool, &CONTRACT.parse().unwrap(), &tokens).await;
+        assert_eq!(held[0].amount, U256::from(4_000_000_000u64));
+        assert_eq!(held[0].value, U256::exp10(18) * 2);
+        assert_eq!(total_value(U256::exp10(18), &held), U256::exp10(18) * 3);
+    }
+}
@@ -0,0 +1,108 @@
+use primitive_types::U256;
+use std::error::Error;
+
+/// Amount of decimals of ether, the native coin of other chains has Chain.decimals
+pub const ETHER_DECIMALS: usize = 18;
+
+/// Parses a 0x prefixed hex quantity, as returned by the json-rpc api, into a 256 bit integer.
+pub fn parse_hex_u256(quantity: &str) -> Result<U256, Box<dyn Error + Send + Sync>> {
+    let digits = quantity.trim_start_matches("0x");
+    if digits.is_empty() || digits.len() > 64 {
+        return Err(format!("invalid quantity: {}", quantity).into());
+    }
+    Ok(U256::from_str_radix(digits, 16)?)
+}
+
+/// Parses a decimal amount like "1.5" into its smallest unit, e.g. wei when decimals is 18.
+pub fn parse_units(amount: &str, decimals: usize) -> Result<U256, Box<dyn Error + Send + Sync>> {
+    let amount = amount.trim();
+    let (whole, fraction) = match amount.find('.') {
+        Some(ix) => (&amount[..ix], &amount[ix + 1..]),
+        None => (amount, ""),
+    };
+
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !all_digits(whole) || !all_digits(fraction) {
+        return Err(format!("invalid amount: {}", amount).into());
+    }
+    if fraction.len() > decimals {
+        return Err(format!("{} has more than {} decimals", amount, decimals).into());
+    }
+
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals);
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(U256::zero());
+    }
+    Ok(U256::from_dec_str(digits).map_err(|e| format!("{:?}", e))?)
+}
+
+/// Formats an amount in its smallest unit as a decimal number, without trailing zeros.
+pub fn format_units(amount: U256, decimals: usize) -> String {
+    let digits = format!("{:0>width$}", amount.to_string(), width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
+pub fn parse_ether(amount: &str) -> Result<U256, Box<dyn Error + Send + Sync>> {
+    parse_units(amount, ETHER_DECIMALS)
+}
+
+pub fn format_ether(wei: U256) -> String {
+    format_units(wei, ETHER_DECIMALS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_u256() {
+        assert_eq!(parse_hex_u256("0x0").unwrap(), U256::zero());
+        assert_eq!(
+            parse_hex_u256("0x4563918244f40000").unwrap(),
+            U256::from(5_000_000_000_000_000_000u128)
+        );
+        assert!(parse_hex_u256("0x").is_err());
+        assert!(parse_hex_u256("0xzz").is_err());
+    }
+
+    #[test]
+    fn test_parse_hex_u256_above_u128() {
+        let max = format!("0x{}", "f".repeat(64));
+        assert_eq!(parse_hex_u256(&max).unwrap(), U256::MAX);
+    }
+
+    #[test]
+    fn test_parse_ether() {
+        assert_eq!(parse_ether("1").unwrap(), U256::exp10(18));
+        assert_eq!(parse_ether("0.5").unwrap(), U256::exp10(17) * 5);
+        assert_eq!(parse_ether(".5").unwrap(), U256::exp10(17) * 5);
+        assert_eq!(parse_ether("0").unwrap(), U256::zero());
+        assert_eq!(parse_ether("0.000000000000000001").unwrap(), U256::one());
+        assert!(parse_ether("0.0000000000000000001").is_err());
+        assert!(parse_ether("-1").is_err());
+        assert!(parse_ether("1e18").is_err());
+        assert!(parse_ether("").is_err());
+    }
+
+    #[test]
+    fn test_format_ether() {
+        assert_eq!(format_ether(U256::exp10(18) * 5), "5");
+        assert_eq!(format_ether(U256::exp10(17) * 5), "0.5");
+        assert_eq!(format_ether(U256::one()), "0.000000000000000001");
+        assert_eq!(format_ether(U256::zero()), "0");
+    }
+
+    #[test]
+    fn test_format_units_roundtrip() {
+        let amount = "123456789.123456";
+        assert_eq!(format_units(parse_units(amount, 6).unwrap(), 6), amount);
+    }
+}